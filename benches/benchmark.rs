@@ -1,6 +1,6 @@
-use criterion::{Criterion, criterion_group, criterion_main};
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
 use iced::Point;
-use kdtree_iced::KDTree;
+use kdtree_iced::{Euclidean, KDTree, KdPoint, Metric, StaticKDTree, distance};
 
 fn random_point() -> Point {
     Point::new(rand::random::<f32>(), rand::random::<f32>())
@@ -13,6 +13,19 @@ pub fn creating_100_000_points(c: &mut Criterion) {
     });
 }
 
+pub fn creating_100_000_points_sequentially(c: &mut Criterion) {
+    c.bench_function("creating_100_000_points_sequentially", |b| {
+        let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+        b.iter(|| {
+            let mut tree = KDTree::default();
+            for point in points.iter() {
+                tree.add_point(*point);
+            }
+            tree
+        })
+    });
+}
+
 pub fn insertion(c: &mut Criterion) {
     let mut tree = KDTree::default();
     for _ in 0..100_000 {
@@ -33,6 +46,218 @@ pub fn nearest_neighbor(c: &mut Criterion) {
     });
 }
 
+pub fn query_throughput(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let targets: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+    let mut tree = KDTree::from_points(&points);
+    // Removals and insertions scatter the nodes in memory
+    for point in points[..50_000].iter() {
+        tree.remove_point(*point);
+    }
+    for _ in 0..50_000 {
+        tree.add_point(random_point());
+    }
+    let mut group = c.benchmark_group("query_throughput");
+    group.throughput(Throughput::Elements(targets.len() as u64));
+    group.bench_function("nearest_neighbor", |b| {
+        b.iter(|| {
+            for target in targets.iter() {
+                tree.nearest_neighbor(target);
+            }
+        })
+    });
+    group.bench_function("k_nearest_neighbors", |b| {
+        b.iter(|| {
+            for target in targets.iter() {
+                tree.k_nearest_neighbors(target, 10);
+            }
+        })
+    });
+    group.bench_function("neighbors_by_distance_take_10", |b| {
+        b.iter(|| {
+            for target in targets.iter() {
+                tree.neighbors_by_distance(target).take(10).for_each(drop);
+            }
+        })
+    });
+    group.finish();
+}
+
+pub fn static_nearest_neighbor(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let targets: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+    let tree = KDTree::from_points(&points);
+    let static_tree = StaticKDTree::from_points(&points);
+    let mut group = c.benchmark_group("static_nearest_neighbor");
+    group.throughput(Throughput::Elements(targets.len() as u64));
+    group.bench_function("kdtree", |b| {
+        b.iter(|| {
+            for target in targets.iter() {
+                tree.nearest_neighbor(target);
+            }
+        })
+    });
+    group.bench_function("static_kdtree", |b| {
+        b.iter(|| {
+            for target in targets.iter() {
+                static_tree.nearest_neighbor(target);
+            }
+        })
+    });
+    group.finish();
+}
+
+pub fn bucket_size(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let targets: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+    let mut group = c.benchmark_group("bucket_size");
+    for bucket_size in [1, 8, 16, 32] {
+        group.bench_with_input(
+            BenchmarkId::new("add_100_000_points", bucket_size),
+            &bucket_size,
+            |b, bucket_size| {
+                b.iter(|| {
+                    let mut tree = KDTree::default().with_bucket_size(*bucket_size);
+                    for point in points.iter() {
+                        tree.add_point(*point);
+                    }
+                    tree
+                })
+            },
+        );
+        let tree = KDTree::from_points(&points).with_bucket_size(bucket_size);
+        group.bench_with_input(
+            BenchmarkId::new("1000_nearest_neighbors", bucket_size),
+            &tree,
+            |b, tree| {
+                b.iter(|| {
+                    for target in targets.iter() {
+                        tree.nearest_neighbor(target);
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Euclidean distance comparing the actual distances, which takes a square root per candidate
+#[derive(Clone, Copy)]
+struct RootedEuclidean;
+
+impl Metric<Point> for RootedEuclidean {
+    type Distance = f32;
+
+    fn distance(&self, a: &Point, b: &Point) -> f32 {
+        distance(a, b)
+    }
+
+    fn plane_distance(&self, point: &Point, axis: usize, value: f32) -> f32 {
+        (point.coord(axis) - value).abs()
+    }
+
+    fn combine(&self, a: f32, b: f32) -> f32 {
+        a.hypot(b)
+    }
+
+    fn to_real(&self, distance: f32) -> f32 {
+        distance
+    }
+
+    fn distance_bound(&self, real: f32) -> f32 {
+        real
+    }
+}
+
+pub fn squared_distance(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let targets: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+    let tree = KDTree::from_points(&points);
+    let mut group = c.benchmark_group("squared_distance");
+    group.throughput(Throughput::Elements(targets.len() as u64));
+    group.bench_function("nearest_neighbor/squared", |b| {
+        let view = tree.using(Euclidean);
+        b.iter(|| {
+            for target in targets.iter() {
+                view.nearest_neighbor(target);
+            }
+        })
+    });
+    group.bench_function("nearest_neighbor/rooted", |b| {
+        let view = tree.using(RootedEuclidean);
+        b.iter(|| {
+            for target in targets.iter() {
+                view.nearest_neighbor(target);
+            }
+        })
+    });
+    group.bench_function("k_nearest_neighbors/squared", |b| {
+        let view = tree.using(Euclidean);
+        b.iter(|| {
+            for target in targets.iter() {
+                view.k_nearest_neighbors(target, 10);
+            }
+        })
+    });
+    group.bench_function("k_nearest_neighbors/rooted", |b| {
+        let view = tree.using(RootedEuclidean);
+        b.iter(|| {
+            for target in targets.iter() {
+                view.k_nearest_neighbors(target, 10);
+            }
+        })
+    });
+    group.finish();
+}
+
+pub fn approx_nearest_neighbor(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let targets: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+    let tree = KDTree::from_points(&points);
+    let mut group = c.benchmark_group("approx_nearest_neighbor");
+    group.throughput(Throughput::Elements(targets.len() as u64));
+    for epsilon in [0., 0.5, 2.] {
+        group.bench_with_input(
+            BenchmarkId::new("epsilon", epsilon),
+            &epsilon,
+            |b, epsilon| {
+                b.iter(|| {
+                    for target in targets.iter() {
+                        tree.approx_nearest_neighbor(target, *epsilon);
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+pub fn farthest_point(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let targets: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+    let tree = KDTree::from_points(&points);
+    let mut group = c.benchmark_group("farthest_point");
+    group.throughput(Throughput::Elements(targets.len() as u64));
+    group.bench_function("kdtree", |b| {
+        b.iter(|| {
+            for target in targets.iter() {
+                tree.farthest_point(target);
+            }
+        })
+    });
+    group.bench_function("scan", |b| {
+        let points = tree.points();
+        b.iter(|| {
+            for target in targets.iter() {
+                points
+                    .iter()
+                    .max_by(|a, b| distance(*a, target).total_cmp(&distance(*b, target)));
+            }
+        })
+    });
+    group.finish();
+}
+
 pub fn deletion(c: &mut Criterion) {
     let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
     let mut tree = KDTree::from_points(&points);
@@ -42,11 +267,108 @@ pub fn deletion(c: &mut Criterion) {
     });
 }
 
+pub fn lazy_deletion(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let mut group = c.benchmark_group("lazy_deletion");
+    group.bench_function("remove_50_000_points/eager", |b| {
+        b.iter_batched(
+            || KDTree::from_points(&points),
+            |mut tree| {
+                for point in points[..50_000].iter() {
+                    tree.remove_point(*point);
+                }
+                tree
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("remove_50_000_points/lazy", |b| {
+        b.iter_batched(
+            || KDTree::from_points(&points).with_lazy_deletion(0.25),
+            |mut tree| {
+                for point in points[..50_000].iter() {
+                    tree.remove_point(*point);
+                }
+                tree
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+pub fn batch_insertion(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let batch: Vec<Point> = (0..10_000).map(|_| random_point()).collect();
+    let mut group = c.benchmark_group("batch_insertion");
+    group.bench_function("add_10_000_points/add_point", |b| {
+        b.iter_batched(
+            || KDTree::from_points(&points),
+            |mut tree| {
+                for point in batch.iter() {
+                    tree.add_point(*point);
+                }
+                tree
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("add_10_000_points/extend", |b| {
+        b.iter_batched(
+            || KDTree::from_points(&points),
+            |mut tree| {
+                tree.extend(&batch);
+                tree
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+pub fn batch_deletion(c: &mut Criterion) {
+    let points: Vec<Point> = (0..100_000).map(|_| random_point()).collect();
+    let mut group = c.benchmark_group("batch_deletion");
+    group.bench_function("remove_50_000_points/remove_point", |b| {
+        b.iter_batched(
+            || KDTree::from_points(&points),
+            |mut tree| {
+                for point in points[..50_000].iter() {
+                    tree.remove_point(*point);
+                }
+                tree
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("remove_50_000_points/remove_many", |b| {
+        b.iter_batched(
+            || KDTree::from_points(&points),
+            |mut tree| {
+                tree.remove_many(&points[..50_000]);
+                tree
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
 criterion_group!(
     benches,
     creating_100_000_points,
+    creating_100_000_points_sequentially,
     insertion,
     nearest_neighbor,
-    deletion
+    query_throughput,
+    static_nearest_neighbor,
+    bucket_size,
+    squared_distance,
+    approx_nearest_neighbor,
+    farthest_point,
+    deletion,
+    lazy_deletion,
+    batch_insertion,
+    batch_deletion
 );
 criterion_main!(benches);
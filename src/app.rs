@@ -26,7 +26,9 @@ impl Default for App {
             Point::new(0.6, 1. - 0.18),
         ];
         Self {
-            tree: kdtree::KDTree::from_points(&points),
+            // Clicking twice on the same pixel does not stack invisible points
+            tree: kdtree::KDTree::from_points(&points)
+                .with_duplicate_policy(kdtree::DuplicatePolicy::Reject),
             nearest_neighbor: None,
             target: None,
         }
@@ -51,21 +53,21 @@ impl App {
             Message::AddPoint(point) => {
                 self.tree.add_point(point);
                 match self.target {
-                    Some(point) => self.nearest_neighbor = self.tree.nearest_neighbor(&point),
+                    Some(point) => self.nearest_neighbor = self.find_neighbor(&point),
                     None => self.nearest_neighbor = None,
                 }
             }
             Message::FindNeighbor(point) => {
-                self.nearest_neighbor = self.tree.nearest_neighbor(&point);
+                self.nearest_neighbor = self.find_neighbor(&point);
                 self.target = Some(point);
             }
             Message::DeletePoint(point) => {
-                if let Some(point_to_remove) = self.tree.nearest_neighbor(&point)
+                if let Some(point_to_remove) = self.find_neighbor(&point)
                     && point_to_remove.distance(point) <= MIN_DISTANCE
                 {
                     self.tree.remove_point(point_to_remove);
                     match self.target {
-                        Some(point) => self.nearest_neighbor = self.tree.nearest_neighbor(&point),
+                        Some(point) => self.nearest_neighbor = self.find_neighbor(&point),
                         None => self.nearest_neighbor = None,
                     }
                 }
@@ -73,6 +75,13 @@ impl App {
         }
     }
 
+    /// Returns the nearest neighbor point from tree's points of the specified point
+    fn find_neighbor(&self, point: &Point) -> Option<Point> {
+        self.tree
+            .nearest_neighbor(point)
+            .map(|(neighbor, _)| neighbor)
+    }
+
     /// Returns the widget displayed on the screen
     pub fn view(&self) -> Element<'_, Message> {
         canvas::Canvas::new(geometry::Geometry::new(
@@ -0,0 +1,141 @@
+use std::ops::{Index, IndexMut};
+
+/// A slot of an `Arena`
+#[derive(Debug)]
+enum Slot<T> {
+    /// Slot holding a value
+    Occupied(T),
+    /// Free slot holding the index of the next free slot of the free-list
+    Free(Option<usize>),
+}
+
+/// Dense storage of values indexed by `usize` backed by a `Vec`. Removed values leave free slots
+/// which are chained into a free-list and reused by the next insertions.
+#[derive(Debug)]
+pub struct Arena<T> {
+    /// Slots of the arena
+    slots: Vec<Slot<T>>,
+    /// Head of the free-list
+    free_head: Option<usize>,
+    /// Number of occupied slots
+    len: usize,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<T> Arena<T> {
+    /// Creates an empty arena which can hold `capacity` values without reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            free_head: None,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of values in the arena
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the arena contains no values
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of free slots
+    pub fn free_len(&self) -> usize {
+        self.slots.len() - self.len
+    }
+
+    /// Inserts a value into the first slot of the free-list if any, otherwise at the end, and
+    /// returns its index.
+    pub fn insert(&mut self, value: T) -> usize {
+        self.len += 1;
+        match self.free_head {
+            Some(index) => {
+                if let Slot::Free(next) = self.slots[index] {
+                    self.free_head = next;
+                }
+                self.slots[index] = Slot::Occupied(value);
+                index
+            }
+            None => {
+                self.slots.push(Slot::Occupied(value));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    /// Removes the value at `index` and pushes its slot at the front of the free-list.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        match self.slots.get(index) {
+            Some(Slot::Occupied(_)) => {
+                self.len -= 1;
+                let slot = std::mem::replace(&mut self.slots[index], Slot::Free(self.free_head));
+                self.free_head = Some(index);
+                match slot {
+                    Slot::Occupied(value) => Some(value),
+                    Slot::Free(_) => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the value at `index`
+    pub fn get(&self, index: usize) -> Option<&T> {
+        match self.slots.get(index) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a mutable reference to the value at `index`
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        match self.slots.get_mut(index) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the values of the arena
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|slot| match slot {
+            Slot::Occupied(value) => Some(value),
+            Slot::Free(_) => None,
+        })
+    }
+
+    /// Consumes the arena and returns an iterator over its values
+    pub fn into_values(self) -> impl Iterator<Item = T> {
+        self.slots.into_iter().filter_map(|slot| match slot {
+            Slot::Occupied(value) => Some(value),
+            Slot::Free(_) => None,
+        })
+    }
+}
+
+impl<T> Index<usize> for Arena<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        match &self.slots[index] {
+            Slot::Occupied(value) => value,
+            Slot::Free(_) => panic!("no value at index {index}"),
+        }
+    }
+}
+
+impl<T> IndexMut<usize> for Arena<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        match &mut self.slots[index] {
+            Slot::Occupied(value) => value,
+            Slot::Free(_) => panic!("no value at index {index}"),
+        }
+    }
+}
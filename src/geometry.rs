@@ -8,7 +8,6 @@ const CIRCLE_RADIUS: f32 = 5.;
 
 #[derive(Debug)]
 pub enum Line {
-    Vertical(f32),
     PointToPoint(Point, Point),
 }
 
@@ -81,18 +80,8 @@ impl canvas::Program<Message> for Geometry {
         let mut frame = canvas::Frame::new(renderer, bounds.size());
 
         for line in self.lines.iter() {
-            let line = match line {
-                Line::Vertical(x) => {
-                    let x = bounds.x + bounds.width * x;
-                    canvas::Path::line(
-                        Point::new(x, bounds.y),
-                        Point::new(x, bounds.y + bounds.height),
-                    )
-                }
-                Line::PointToPoint(from, to) => {
-                    canvas::Path::line(scale(from, &bounds), scale(to, &bounds))
-                }
-            };
+            let Line::PointToPoint(from, to) = line;
+            let line = canvas::Path::line(scale(from, &bounds), scale(to, &bounds));
             frame.stroke(
                 &line,
                 canvas::Stroke::default()
@@ -1,353 +1,2752 @@
 use core::f32;
-use std::collections::{HashMap, VecDeque};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
 
+use crate::arena::Arena;
 use crate::geometry;
+use crate::metric::{Euclidean, Metric};
+use crate::point::{Float, KdPoint, Real, Scalar};
 use iced::{Point, Rectangle};
 
-/// Split direction of points
-#[derive(Debug)]
-enum Split {
-    X,
-    Y,
-}
-
-impl Split {
-    /// Returns the opposite direction of the split
-    fn opposite(&self) -> Self {
-        match self {
-            Split::Y => Split::X,
-            Split::X => Split::Y,
-        }
-    }
-}
+/// Balance factor $\alpha$ of the tree. A subtree is $\alpha$-balanced when none of its children
+/// holds more than $\alpha$ times its size.
+const ALPHA: f32 = 0.75;
 
 /// A node structure used by `KDTree`
 #[derive(Debug)]
-struct Node {
-    /// Point of the node
-    point: Point,
+struct Node<P, V> {
+    /// Point giving the split plane of the node, which is the point of `entry` while the node
+    /// is alive
+    point: P,
+    /// Point of the node with its value, `None` once it is removed lazily: the node is then dead
+    /// and only keeps its split plane
+    entry: Option<Entry<P, V>>,
+    /// Other points equal to `point`, grouped into the node so that equal points do not make the
+    /// tree deeper (always empty for dead nodes)
+    duplicates: Vec<Entry<P, V>>,
+    /// Other points of a leaf node, scanned linearly (always empty for other nodes)
+    bucket: Vec<Entry<P, V>>,
     /// Left child node of the node
     left: Option<usize>,
     /// Right child node of the node
     right: Option<usize>,
-    /// Split direction
-    split: Split,
+    /// Split axis, i.e. the index of the coordinate compared to choose the child node
+    split: usize,
+    /// Number of points in the subtree starting from this node (its own points included)
+    size: usize,
 }
 
-impl Node {
-    /// Checks if distance (`radius` of the hypersphere) is greater than the absolute distance
-    /// between the point and the current node point:
+impl<P: KdPoint, V> Node<P, V> {
+    /// Checks if the distance `radius` (of the ball centered on the target point) is greater
+    /// than the distance between the point and the split plane of the current node:
     ///
     /// $$
-    /// d(T, P) > |\overrightarrow{NT} \cdot \overrightarrow{\text{dir}}|
+    /// d(T, P) > d(T, \Pi_N)
     /// $$
     ///
     /// where:
     /// - $T$ is the target point (`point`)
-    /// - $P$ is the current best neighbor, $d(T, P)$ is the euclidian distance between $T$ and $P$
-    ///   (`radius`)
-    /// - $N$ is the node point (`self.point`)
-    /// - $\overrightarrow{\text{dir}}$ is the split direction (i.e. $\vec x$ or $\vec y$)
-    fn is_in_hypersphere(&self, point: &Point, radius: f32) -> bool {
-        match self.split {
-            Split::X => radius > (point.x - self.point.x).abs(),
-            Split::Y => radius > (point.y - self.point.y).abs(),
-        }
+    /// - $P$ is the current best neighbor, $d(T, P)$ is its distance to $T$ (`radius`)
+    /// - $\Pi_N$ is the split plane of the node going through the node point (`self.point`)
+    ///
+    /// Distances are compared as [`Metric::Distance`], e.g. squared distances for [`Euclidean`]
+    /// so that no precision is lost with integer coordinates.
+    fn is_in_ball<M: Metric<P>>(&self, point: &P, radius: M::Distance, metric: &M) -> bool {
+        radius > self.plane_distance(point, metric)
+    }
+
+    /// Returns the distance between the point and the split plane of the node
+    fn plane_distance<M: Metric<P>>(&self, point: &P, metric: &M) -> M::Distance {
+        metric.plane_distance(point, self.split, self.point.coord(self.split))
     }
 
     /// Returns the direction of the next node child given the specified point where `true`
     /// represents "left" and `false` represents "right".
-    fn direction(&self, point: &Point) -> bool {
-        match self.split {
-            Split::X => point.x <= self.point.x,
-            Split::Y => point.y <= self.point.y,
+    fn direction(&self, point: &P) -> bool {
+        point.coord(self.split) <= self.point.coord(self.split)
+    }
+
+    /// Returns `true` if the node has no children
+    fn is_leaf(&self) -> bool {
+        self.left.is_none() && self.right.is_none()
+    }
+
+    /// Returns `true` if the point of the node was removed lazily
+    fn is_dead(&self) -> bool {
+        self.entry.is_none()
+    }
+
+    /// Returns an iterator over the entries of the node, starting with the one of its split plane
+    /// unless the node is dead, followed by the entries equal to it and by the bucket
+    fn entries(&self) -> impl Iterator<Item = &Entry<P, V>> {
+        self.entry
+            .iter()
+            .chain(self.duplicates.iter())
+            .chain(self.bucket.iter())
+    }
+
+    /// Returns the entry of the node whose handle slot is `id`
+    fn find_entry(&self, id: usize) -> Option<&Entry<P, V>> {
+        self.entries().find(|entry| entry.id == id)
+    }
+
+    /// Returns a mutable reference to the entry of the node whose handle slot is `id`
+    fn find_entry_mut(&mut self, id: usize) -> Option<&mut Entry<P, V>> {
+        self.entry
+            .iter_mut()
+            .chain(self.duplicates.iter_mut())
+            .chain(self.bucket.iter_mut())
+            .find(|entry| entry.id == id)
+    }
+}
+
+/// A point with its value and the index of its handle slot, moved between nodes when the tree is
+/// rebuilt
+#[derive(Debug)]
+struct Entry<P, V> {
+    /// Point of the entry
+    point: P,
+    /// Value associated with the point
+    value: V,
+    /// Index of the slot of the point handle
+    id: usize,
+}
+
+impl<P: KdPoint, V> Entry<P, V> {
+    /// Returns the point of the entry with a reference to its value
+    fn pair(&self) -> (P, &V) {
+        (self.point, &self.value)
+    }
+}
+
+/// Stable handle of a point returned when it is inserted into a `KDTree`.
+///
+/// A handle stays valid while the point belongs to the tree, even when nodes are rebuilt. Slots of
+/// removed points are reused, but the generation of a slot is incremented on removal so that an
+/// old handle never refers to a newer point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PointId {
+    /// Index of the slot
+    index: usize,
+    /// Generation of the slot when the handle was created
+    generation: u32,
+}
+
+/// Outcome of [`KDTree::insert`], which depends on the [`DuplicatePolicy`] of the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Insertion<V> {
+    /// The point was inserted and has the handle
+    Inserted(PointId),
+    /// An equal point of the tree has the handle, and the point was not inserted: the rejected
+    /// value is given back
+    Rejected(PointId, V),
+    /// An equal point of the tree has the handle, and its value was replaced: the old value is
+    /// given back
+    Replaced(PointId, V),
+}
+
+impl<V> Insertion<V> {
+    /// Returns the handle of the point holding the inserted value, or of the equal point which
+    /// rejected it
+    pub fn id(&self) -> PointId {
+        match self {
+            Insertion::Inserted(id) | Insertion::Rejected(id, _) | Insertion::Replaced(id, _) => {
+                *id
+            }
+        }
+    }
+
+    /// Returns `true` if the point was inserted as a new point
+    pub fn is_inserted(&self) -> bool {
+        matches!(self, Insertion::Inserted(_))
+    }
+}
+
+/// Error returned by the fallible mutations of a `KDTree`, such as [`KDTree::try_insert`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KDTreeError {
+    /// The point or the handle does not belong to the tree
+    NotFound,
+    /// A coordinate of the point is NaN or infinite
+    NonFiniteCoordinate,
+    /// An equal point of the tree has the handle, and the [`DuplicatePolicy`] of the tree does
+    /// not allow another one
+    Duplicate(PointId),
+}
+
+impl std::fmt::Display for KDTreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KDTreeError::NotFound => write!(f, "point not found in the tree"),
+            KDTreeError::NonFiniteCoordinate => write!(f, "point has a non-finite coordinate"),
+            KDTreeError::Duplicate(_) => write!(f, "an equal point is already in the tree"),
         }
     }
 }
 
-/// KDTree structure with [`iced::Point`](https://docs.rs/iced/latest/iced/struct.Point.html) in
-/// `Node`
-#[derive(Default, Debug)]
-pub struct KDTree {
-    /// Free indices when a node is removed
-    free_indices: VecDeque<usize>,
-    /// Node collection
-    nodes: HashMap<usize, Node>,
+impl std::error::Error for KDTreeError {}
+
+/// A slot of a point handle
+#[derive(Debug)]
+struct Slot {
+    /// Current generation of the slot
+    generation: u32,
+    /// Index of the node holding the point, `None` if the slot is free
+    node: Option<usize>,
+}
+
+/// A candidate neighbor ordered by its distance to the target point, used as an element of the
+/// bounded max-heap of `KDTree::k_nearest_neighbors`
+#[derive(Debug)]
+struct Neighbor<'a, P, V, D> {
+    /// Distance between the target point and the candidate
+    distance: D,
+    /// Entry of the candidate
+    entry: &'a Entry<P, V>,
+}
+
+impl<P, V, D: PartialOrd> PartialEq for Neighbor<'_, P, V, D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<P, V, D: PartialOrd> Eq for Neighbor<'_, P, V, D> {}
+
+impl<P, V, D: PartialOrd> PartialOrd for Neighbor<'_, P, V, D> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P, V, D: PartialOrd> Ord for Neighbor<'_, P, V, D> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// State of an approximate nearest neighbor search, see `KDTree::approx_nearest_neighbor`
+#[derive(Debug)]
+struct ApproxSearch<'a, P, V, D, R> {
+    /// Best candidate found so far with its distance to the target point
+    best: Option<(&'a Entry<P, V>, D)>,
+    /// Distance of the best candidate shrunk by the factor $1 + \varepsilon$, used for pruning
+    bound: Option<D>,
+    /// Factor $1 + \varepsilon$, `None` when $\varepsilon = 0$ so that the search is exact
+    factor: Option<R>,
+    /// Maximum number of nodes to examine
+    max_visited: usize,
+    /// Number of nodes examined
+    visited: usize,
+}
+
+/// A node to expand or an entry to return by `NeighborsByDistance`
+#[derive(Debug)]
+enum Candidate<'a, P, V> {
+    /// Index of a node
+    Node(usize),
+    /// Entry of a point
+    Entry(&'a Entry<P, V>),
+}
+
+/// A candidate of the priority queue of `NeighborsByDistance`, ordered so that the closest
+/// candidate is at the top of the max-heap
+#[derive(Debug)]
+struct Queued<'a, P, V, D> {
+    /// Distance between the target point and the point of an entry, or lower bound of the
+    /// distance between the target point and the cell of a node
+    distance: D,
+    /// Queued node or entry
+    candidate: Candidate<'a, P, V>,
+}
+
+impl<P, V, D: PartialOrd> PartialEq for Queued<'_, P, V, D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<P, V, D: PartialOrd> Eq for Queued<'_, P, V, D> {}
+
+impl<P, V, D: PartialOrd> PartialOrd for Queued<'_, P, V, D> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P, V, D: PartialOrd> Ord for Queued<'_, P, V, D> {
+    /// Reverses the order of the distances, and puts entries before nodes at the same distance
+    /// so that they are returned without expanding more nodes.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| {
+                let is_entry = |queued: &Self| matches!(queued.candidate, Candidate::Entry(_));
+                is_entry(self).cmp(&is_entry(other))
+            })
+    }
+}
+
+/// KDTree structure of $K$-dimensional points implementing [`KdPoint`], each point carrying a
+/// value of type `V`. By default, the points are
+/// [`iced::Point`](https://docs.rs/iced/latest/iced/struct.Point.html) without value in `Node`,
+/// so `KDTree` alone is the 2D tree used by the visualization.
+#[derive(Debug)]
+pub struct KDTree<P: KdPoint = Point, V = ()> {
+    /// Node collection, where the slots of removed nodes are reused through a free-list
+    nodes: Arena<Node<P, V>>,
     /// Root index (not necessary `0`)
     root_index: usize,
+    /// Maximum size reached by the tree since its last full rebuild
+    max_size: usize,
+    /// Maximum number of points held by a leaf node
+    bucket_size: usize,
+    /// Strategy choosing the split axis of the nodes
+    split_strategy: SplitStrategy,
+    /// Behavior when inserting a point equal to a point of the tree
+    duplicate_policy: DuplicatePolicy,
+    /// Fraction of dead nodes above which the tree is rebuilt, `None` if points are removed
+    /// eagerly
+    max_dead_fraction: Option<f32>,
+    /// Number of dead nodes
+    dead_len: usize,
+    /// Slots of the point handles
+    slots: Vec<Slot>,
+    /// Free slot indices when a point is removed
+    free_slots: VecDeque<usize>,
+}
+
+impl<P: KdPoint, V> Default for KDTree<P, V> {
+    fn default() -> Self {
+        Self {
+            nodes: Arena::default(),
+            root_index: 0,
+            max_size: 0,
+            bucket_size: 1,
+            split_strategy: SplitStrategy::Alternating,
+            duplicate_policy: DuplicatePolicy::Allow,
+            max_dead_fraction: None,
+            dead_len: 0,
+            slots: Vec::new(),
+            free_slots: VecDeque::new(),
+        }
+    }
+}
+
+impl<P: KdPoint> KDTree<P> {
+    /// Builds a balanced `KDTree` from points in $O(n \cdot \log_2(n))$ where $n$ is the number of
+    /// points. Each node is the median of its points along its split direction, hence the depth
+    /// of the tree is at most $\lceil \log_2(n + 1) \rceil$ (unless many points share the same
+    /// coordinate).
+    pub fn from_points(points: &[P]) -> Self {
+        KDTree::from_entries(points.iter().map(|point| (*point, ())).collect())
+    }
+
+    /// Adds a point without value. See [`KDTree::insert`].
+    pub fn add_point(&mut self, point: P) -> Insertion<()> {
+        self.insert(point, ())
+    }
+
+    /// Adds a point without value, or fails. See [`KDTree::try_insert`].
+    pub fn try_add_point(&mut self, point: P) -> Result<PointId, KDTreeError> {
+        self.try_insert(point, ())
+    }
+
+    /// Adds a batch of points without value. See [`KDTree::extend_entries`].
+    pub fn extend(&mut self, points: &[P]) -> Vec<Insertion<()>> {
+        self.extend_entries(points.iter().map(|point| (*point, ())).collect())
+    }
 }
 
-impl KDTree {
-    /// Builds a `KDTree` from points
-    pub fn from_points(points: &[Point]) -> Self {
+impl<P: KdPoint, V> KDTree<P, V> {
+    /// Builds a balanced `KDTree` from points and their values. See [`KDTree::from_points`].
+    pub fn from_entries(entries: Vec<(P, V)>) -> Self {
         let mut tree = KDTree::default();
-        for point in points {
-            tree.add_point(*point);
+        let entries = entries
+            .into_iter()
+            .map(|(point, value)| Entry {
+                point,
+                value,
+                id: tree.allocate_slot(),
+            })
+            .collect();
+        if let Some(root_index) = tree.build(entries, 0) {
+            tree.root_index = root_index;
         }
+        tree.max_size = tree.len();
         tree
     }
 
-    /// Adds a point in $O(\log_2(n))$ where $n$ is the size of the tree.
-    pub fn add_point(&mut self, point: Point) {
-        if self.nodes.is_empty() {
-            self.root_index = if let Some(index) = self.free_indices.pop_front() {
-                index
-            } else {
-                self.nodes.len()
-            };
-            self.nodes.insert(
-                self.root_index,
-                Node {
-                    point,
-                    left: None,
-                    right: None,
-                    split: Split::X,
-                },
-            );
-        } else {
-            let node_index = self.find_node(&point, self.root_index);
-            let next_index = if let Some(index) = self.free_indices.pop_front() {
-                index
-            } else {
-                self.nodes.len()
-            };
-
-            self.nodes.entry(node_index).and_modify(|node| {
-                if node.direction(&point) {
-                    node.left = Some(next_index)
-                } else {
-                    node.right = Some(next_index)
-                };
-            });
+    /// Sets the maximum number of points held by a leaf node and rebuilds the tree if it is not
+    /// empty. Leaves hold up to `bucket_size` points scanned linearly and are split at their
+    /// median once full, which reduces the number of nodes and the depth of the tree. The default
+    /// bucket size is `1`, where each node holds a single point.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_size` is `0`.
+    pub fn with_bucket_size(mut self, bucket_size: usize) -> Self {
+        assert!(bucket_size > 0, "bucket size must be at least 1");
+        self.bucket_size = bucket_size;
+        if !self.nodes.is_empty() {
+            self.rebuild(self.root_index, None, false);
+        }
+        self
+    }
 
-            let node = &self.nodes[&node_index];
-            self.nodes.insert(
-                next_index,
-                Node {
-                    point,
-                    left: None,
-                    right: None,
-                    split: node.split.opposite(),
-                },
-            );
+    /// Sets the strategy choosing the split axis of the nodes and rebuilds the tree if it is not
+    /// empty. The strategy applies whenever a subtree is built: on construction, on rebuilds and
+    /// when a full leaf is split. A node created by a single insertion is split along the axis
+    /// following the one of its parent until its subtree is rebuilt. The default strategy is
+    /// [`SplitStrategy::Alternating`].
+    pub fn with_split_strategy(mut self, split_strategy: SplitStrategy) -> Self {
+        self.split_strategy = split_strategy;
+        if !self.nodes.is_empty() {
+            self.rebuild(self.root_index, None, false);
         }
+        self
     }
 
-    /// Removes a point in $O(m \cdot \log_2(m))$ where `m` is the number of recomputed points.
-    /// When removing a point, all nodes under right leaf and left leaf of the removed points are
-    /// recomputed.
-    pub fn remove_point(&mut self, point: Point) {
-        if let Some((node_index, parent_index)) =
-            self.find_parent(point, self.root_index, self.root_index)
-        {
-            if node_index != parent_index {
-                self.nodes.entry(parent_index).and_modify(|node| {
-                    if Some(node_index) == node.left {
-                        node.left = None;
-                    }
-                    if Some(node_index) == node.right {
-                        node.right = None;
-                    }
-                });
+    /// Sets the behavior when inserting a point equal to a point of the tree. The default policy
+    /// is [`DuplicatePolicy::Allow`]. The policy only applies to the next insertions: equal
+    /// points already in the tree are kept.
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// Enables lazy deletion: removing the point of a node with children marks the node as dead
+    /// instead of rebuilding its subtree, so that removals take $O(\log_2(n))$. A dead node keeps
+    /// its split plane but is skipped by the queries. The whole tree is rebuilt without its dead
+    /// nodes once they exceed the fraction `max_dead_fraction` of the nodes and points.
+    ///
+    /// Removing a point from a leaf, or the last point of a subtree, never leaves a dead node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_dead_fraction` is not between `0` and `1`.
+    pub fn with_lazy_deletion(mut self, max_dead_fraction: f32) -> Self {
+        assert!(
+            (0. ..=1.).contains(&max_dead_fraction),
+            "fraction of dead nodes must be between 0 and 1"
+        );
+        self.max_dead_fraction = Some(max_dead_fraction);
+        self
+    }
+
+    /// Returns the number of points in the tree, which excludes the dead nodes
+    pub fn len(&self) -> usize {
+        self.nodes.get(self.root_index).map_or(0, |root| root.size)
+    }
+
+    /// Returns the number of dead nodes, i.e. of points removed lazily which still give the
+    /// split plane of a node. See [`KDTree::with_lazy_deletion`].
+    pub fn dead_len(&self) -> usize {
+        self.dead_len
+    }
+
+    /// Returns `true` if the tree contains no points
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Builds a subtree from `entries` and returns the index of its root. The nodes are split
+    /// according to the split strategy, which gives a balanced subtree unless it is
+    /// [`SplitStrategy::SlidingMidpoint`]. Up to `bucket_size` entries are stored into a single
+    /// leaf, and the entries equal to the point of a node are grouped into it. The subtrees left
+    /// to build are kept on a stack rather than built recursively.
+    fn build(&mut self, entries: Vec<Entry<P, V>>, split: usize) -> Option<usize> {
+        let mut root_index = None;
+        let mut pending = vec![(entries, split, None)];
+        while let Some((mut entries, mut split, parent)) = pending.pop() {
+            let size = entries.len();
+            if size == 0 {
+                continue;
+            }
+            let right_entries = (size > self.bucket_size).then(|| {
+                let (axis, median) = self.split_strategy.split(&mut entries, split);
+                split = axis;
+                entries.split_off(median + 1)
+            });
+            let entry = entries.pop()?;
+            let (duplicates, entries): (Vec<_>, Vec<_>) = entries
+                .into_iter()
+                .partition(|other| other.point == entry.point);
+            let (bucket, children) = match right_entries {
+                Some(right_entries) => (Vec::new(), Some((entries, right_entries))),
+                None => (entries, None),
+            };
+            let node_index = self.insert_node(Node {
+                point: entry.point,
+                entry: Some(entry),
+                duplicates,
+                bucket,
+                left: None,
+                right: None,
+                split,
+                size,
+            });
+            match parent {
+                Some((parent_index, direction)) => {
+                    let parent = &mut self.nodes[parent_index];
+                    if direction {
+                        parent.left = Some(node_index)
+                    } else {
+                        parent.right = Some(node_index)
+                    };
+                }
+                None => root_index = Some(node_index),
             }
-            let mut points = Vec::new();
-            self.pop_nodes(node_index, &mut points);
-            for point in points[1..].iter() {
-                self.add_point(*point);
+            if let Some((left_entries, right_entries)) = children {
+                pending.push((
+                    left_entries,
+                    next_split::<P>(split),
+                    Some((node_index, true)),
+                ));
+                pending.push((
+                    right_entries,
+                    next_split::<P>(split),
+                    Some((node_index, false)),
+                ));
             }
         }
+        root_index
     }
 
-    /// Returns the parent index and the node index related to the specified point. The point must
-    /// belong to the tree's points because `node.point` is compared with `point`.
-    fn find_parent(
-        &self,
-        point: Point,
-        node_index: usize,
-        parent_index: usize,
-    ) -> Option<(usize, usize)> {
-        if let Some(node) = self.nodes.get(&node_index) {
-            if node.point == point {
-                Some((node_index, parent_index))
-            } else {
-                match self.single_search(&point, node_index) {
-                    Some(index) => self.find_parent(point, index, node_index),
-                    None => None,
-                }
-            }
+    /// Inserts a node into the collection by reusing a free index if any and returns its index.
+    /// The slots of the point handles are updated to refer to the node.
+    fn insert_node(&mut self, node: Node<P, V>) -> usize {
+        let index = self.nodes.insert(node);
+        self.link_slots(index);
+        index
+    }
+
+    /// Updates the slots of the point handles of the node at `node_index` to refer to the node.
+    fn link_slots(&mut self, node_index: usize) {
+        for entry in self.nodes[node_index].entries() {
+            self.slots[entry.id].node = Some(node_index);
+        }
+    }
+
+    /// Returns the index of a free slot for a new point handle by reusing a free slot if any.
+    fn allocate_slot(&mut self) -> usize {
+        if let Some(index) = self.free_slots.pop_front() {
+            index
         } else {
-            None
+            self.slots.push(Slot {
+                generation: 0,
+                node: None,
+            });
+            self.slots.len() - 1
         }
     }
 
-    /// Removes all nodes starting from `node_index` and store them into `points`.
-    fn pop_nodes(&mut self, node_index: usize, points: &mut Vec<Point>) {
-        if let Some(node) = self.nodes.remove(&node_index) {
-            points.push(node.point);
-            self.free_indices.push_back(node_index);
-            if let Some(left_index) = node.left {
-                self.pop_nodes(left_index, points);
-            }
-            if let Some(right_index) = node.right {
-                self.pop_nodes(right_index, points);
-            }
+    /// Frees the slot of a removed point and increments its generation so that existing handles
+    /// become invalid.
+    fn release_slot(&mut self, index: usize) {
+        let slot = &mut self.slots[index];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.node = None;
+        self.free_slots.push_back(index);
+    }
+
+    /// Returns the handle of the point of `entry`
+    fn point_id(&self, entry: &Entry<P, V>) -> PointId {
+        PointId {
+            index: entry.id,
+            generation: self.slots[entry.id].generation,
         }
     }
 
-    /// Finds the depthest node of the tree given the specified `point`. Note that `node_index`
-    /// should be `self.root_index`.
-    fn find_node(&self, point: &Point, node_index: usize) -> usize {
-        match self.single_search(point, node_index) {
-            Some(index) => self.find_node(point, index),
-            None => node_index,
+    /// Returns the index of the node holding the point of the specified handle, if it is valid.
+    fn node_index(&self, id: PointId) -> Option<usize> {
+        self.slots
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node)
+    }
+
+    /// Inserts a point with its value in amortized $O(\log_2(n))$ where $n$ is the size of the
+    /// tree.
+    ///
+    /// When the depth of the new node exceeds $\lfloor \log_{1/\alpha}(\lceil n / b \rceil) \rfloor$
+    /// where $b$ is the bucket size, the deepest ancestor which is not $\alpha$-balanced (the
+    /// scapegoat) is rebuilt. A point equal to the point of a node on its search path is grouped
+    /// into this node instead, so that equal points never make the tree deeper.
+    ///
+    /// When the tree already holds a point equal to `point`, the outcome depends on the
+    /// [`DuplicatePolicy`] of the tree. Returns the handle of the inserted point, or of the equal
+    /// point with the rejected or replaced value.
+    pub fn insert(&mut self, point: P, value: V) -> Insertion<V> {
+        if self.duplicate_policy != DuplicatePolicy::Allow
+            && let Some((path, id)) = self.find_point_path(&point)
+        {
+            let node_index = path[path.len() - 1];
+            let existing =
+                self.point_id(self.nodes[node_index].find_entry(id).expect("found entry"));
+            return match self.duplicate_policy {
+                DuplicatePolicy::Replace => {
+                    let entry = self.nodes[node_index]
+                        .find_entry_mut(id)
+                        .expect("found entry");
+                    Insertion::Replaced(existing, std::mem::replace(&mut entry.value, value))
+                }
+                _ => Insertion::Rejected(existing, value),
+            };
         }
+        let id = self.allocate_slot();
+        self.insert_entry(Entry { point, value, id });
+        Insertion::Inserted(PointId {
+            index: id,
+            generation: self.slots[id].generation,
+        })
     }
 
-    /// Returns the next node to traverse given a specified `point` and the current `node_index`.
-    fn single_search(&self, point: &Point, node_index: usize) -> Option<usize> {
-        let node = &self.nodes[&node_index];
-        if node.direction(point) {
-            node.left
-        } else {
-            node.right
+    /// Inserts a point with its value like [`KDTree::insert`], or fails with:
+    ///
+    /// - [`KDTreeError::NonFiniteCoordinate`] if a coordinate of `point` is NaN or infinite,
+    ///   since it cannot be compared with the split planes,
+    /// - [`KDTreeError::Duplicate`] if an equal point is rejected by
+    ///   [`DuplicatePolicy::Reject`].
+    ///
+    /// With [`DuplicatePolicy::Replace`], the old value of the equal point is dropped; use
+    /// [`KDTree::insert`] to get it back.
+    pub fn try_insert(&mut self, point: P, value: V) -> Result<PointId, KDTreeError> {
+        check_finite(&point)?;
+        match self.insert(point, value) {
+            Insertion::Rejected(id, _) => Err(KDTreeError::Duplicate(id)),
+            insertion => Ok(insertion.id()),
         }
     }
 
-    /// Finds the nearest neighbor of the specified `point`.
-    pub fn nearest_neighbor(&self, point: &Point) -> Option<Point> {
+    /// Inserts a batch of points with their values and returns the outcomes in the same order.
+    ///
+    /// The batch is dispatched down the tree at once instead of searching a leaf for each point:
+    /// a subtree is rebuilt balanced with its share of the batch when one of its children would
+    /// hold more than $\alpha$ times its size, or when it is a leaf with a bucket or a dead leaf,
+    /// and a missing child is built from its share of the batch. The points of the batch equal to
+    /// the point of a node are grouped into it. Adding $m$ points takes
+    /// $O(m \cdot \log_2(m + n))$ plus the rebuilds, and the new points do not degrade the
+    /// balance of the tree.
+    ///
+    /// Unless the duplicate policy is [`DuplicatePolicy::Allow`], the points are inserted one by
+    /// one with [`KDTree::insert`] so that the points of the batch are compared with each other.
+    pub fn extend_entries(&mut self, entries: Vec<(P, V)>) -> Vec<Insertion<V>> {
+        if self.duplicate_policy != DuplicatePolicy::Allow {
+            return entries
+                .into_iter()
+                .map(|(point, value)| self.insert(point, value))
+                .collect();
+        }
+        let entries: Vec<Entry<P, V>> = entries
+            .into_iter()
+            .map(|(point, value)| Entry {
+                point,
+                value,
+                id: self.allocate_slot(),
+            })
+            .collect();
+        let insertions = entries
+            .iter()
+            .map(|entry| Insertion::Inserted(self.point_id(entry)))
+            .collect();
         if self.nodes.is_empty() {
-            None
-        } else {
-            Some(self.nearest_neighbor_search(point, self.root_index))
+            if let Some(root_index) = self.build(entries, 0) {
+                self.root_index = root_index;
+            }
+        } else if !entries.is_empty() {
+            let mut entries = entries;
+            self.extend_subtree(self.root_index, None, &mut entries, 0);
         }
+        self.max_size = self.max_size.max(self.len());
+        insertions
     }
 
-    /// Searchs the nearest neighbor recursively
-    fn nearest_neighbor_search(&self, point: &Point, node_index: usize) -> Point {
-        let node = &self.nodes[&node_index];
-        let (primary, secondary) = if node.direction(point) {
-            (node.left, node.right)
-        } else {
-            (node.right, node.left)
-        };
-
-        let (mut best_point, mut best_distance) = match primary {
-            Some(idx) => {
-                let p = self.nearest_neighbor_search(point, idx);
-                (p, point.distance(p))
+    /// Inserts the batch `entries[start..]` into the subtree starting from `node_index`, whose
+    /// parent is `parent_index`, and drains it from `entries`. The batch is partitioned in place
+    /// and the right share is inserted first, so that the share of each subtree is always at the
+    /// end of `entries` when it is drained. See [`KDTree::extend_entries`].
+    fn extend_subtree(
+        &mut self,
+        node_index: usize,
+        parent_index: Option<usize>,
+        entries: &mut Vec<Entry<P, V>>,
+        start: usize,
+    ) {
+        // The entries equal to the point of the node are grouped into it
+        let mut index = start;
+        while index < entries.len() {
+            let node = &mut self.nodes[node_index];
+            if entries[index].point != node.point {
+                index += 1;
+                continue;
             }
-            None => (node.point, point.distance(node.point)),
-        };
+            let entry = entries.swap_remove(index);
+            self.slots[entry.id].node = Some(node_index);
+            node.size += 1;
+            if node.is_dead() {
+                node.entry = Some(entry);
+                self.dead_len -= 1;
+            } else {
+                node.duplicates.push(entry);
+            }
+        }
+        if start == entries.len() {
+            return;
+        }
 
-        if let Some(secondary_index) = secondary
-            && node.is_in_hypersphere(point, best_distance)
-        {
-            let secondary_best = self.nearest_neighbor_search(point, secondary_index);
-            let dist = point.distance(secondary_best);
-            if dist < best_distance {
-                best_point = secondary_best;
-                best_distance = dist;
+        let node = &self.nodes[node_index];
+        let batch = &mut entries[start..];
+        let mut left_len = 0;
+        for index in 0..batch.len() {
+            if node.direction(&batch[index].point) {
+                batch.swap(index, left_len);
+                left_len += 1;
             }
         }
+        let child_size = |child: Option<usize>| child.map_or(0, |index| self.nodes[index].size);
+        let size = node.size + batch.len();
+        let largest =
+            (child_size(node.left) + left_len).max(child_size(node.right) + batch.len() - left_len);
+        let rebuild_leaf = node.is_leaf() && (self.bucket_size > 1 || node.is_dead());
+        if rebuild_leaf || largest as f32 > ALPHA * size as f32 {
+            let split = node.split;
+            let mut entries = entries.split_off(start);
+            self.pop_nodes(node_index, &mut entries);
+            let subtree_index = self.build(entries, split);
+            self.link_subtree(node_index, parent_index, subtree_index);
+            return;
+        }
 
-        let node_distance = point.distance(node.point);
-        if node_distance < best_distance {
-            node.point
-        } else {
-            best_point
+        let (left, right, split) = (node.left, node.right, node.split);
+        self.nodes[node_index].size = size;
+        for (child, start, is_left) in [(right, start + left_len, false), (left, start, true)] {
+            if start == entries.len() {
+                continue;
+            }
+            match child {
+                Some(child_index) => {
+                    self.extend_subtree(child_index, Some(node_index), entries, start)
+                }
+                None => {
+                    let subtree_index =
+                        self.build(entries.split_off(start), next_split::<P>(split));
+                    let node = &mut self.nodes[node_index];
+                    if is_left {
+                        node.left = subtree_index;
+                    } else {
+                        node.right = subtree_index;
+                    }
+                }
+            }
         }
     }
 
-    /// Store lines into `lines` by traversing the tree using a Depth First Search approach
-    fn dfs_lines(&self, node_index: usize, lines: &mut Vec<geometry::Line>, bounds: Rectangle) {
-        let node = &self.nodes[&node_index];
-        if let Some(index) = node.left {
-            let left = &self.nodes[&index];
-            match left.split {
-                Split::X => {
-                    lines.push(geometry::Line::PointToPoint(
-                        Point::new(left.point.x, node.point.y),
-                        Point::new(left.point.x, bounds.y),
-                    ));
-                    let bounds = Rectangle {
-                        height: node.point.y,
-                        ..bounds
-                    };
-                    self.dfs_lines(index, lines, bounds);
+    /// Inserts an entry whose handle slot is already allocated. See [`KDTree::insert`].
+    ///
+    /// The entry is grouped into the node of the search path holding an equal point if any, which
+    /// revives the node if it is dead. Otherwise, it is added to the bucket of the leaf reached by
+    /// the search when it is not full. A full leaf is split into a balanced subtree, otherwise a
+    /// new leaf is attached to the last node of the search.
+    fn insert_entry(&mut self, entry: Entry<P, V>) {
+        if self.nodes.is_empty() {
+            self.root_index = self.insert_node(Node {
+                point: entry.point,
+                entry: Some(entry),
+                duplicates: Vec::new(),
+                bucket: Vec::new(),
+                left: None,
+                right: None,
+                split: 0,
+                size: 1,
+            });
+        } else {
+            let path = self.find_path(&entry.point);
+            if let Some(position) = path
+                .iter()
+                .position(|index| self.nodes[*index].point == entry.point)
+            {
+                for index in path[..=position].iter() {
+                    self.nodes[*index].size += 1;
                 }
-                Split::Y => {
-                    lines.push(geometry::Line::PointToPoint(
-                        Point::new(node.point.x, left.point.y),
-                        Point::new(bounds.x, left.point.y),
-                    ));
-                    let bounds = Rectangle {
-                        width: node.point.x,
-                        ..bounds
-                    };
-                    self.dfs_lines(index, lines, bounds);
+                self.slots[entry.id].node = Some(path[position]);
+                let node = &mut self.nodes[path[position]];
+                if node.is_dead() {
+                    node.entry = Some(entry);
+                    self.dead_len -= 1;
+                } else {
+                    node.duplicates.push(entry);
                 }
+                self.max_size = self.max_size.max(self.len());
+                return;
             }
-        }
-        if let Some(index) = node.right {
-            let right = &self.nodes[&index];
-            match right.split {
-                Split::X => {
-                    lines.push(geometry::Line::PointToPoint(
-                        Point::new(right.point.x, node.point.y),
-                        Point::new(right.point.x, bounds.height),
-                    ));
-                    let bounds = Rectangle {
-                        y: node.point.y,
-                        ..bounds
-                    };
-                    self.dfs_lines(index, lines, bounds);
+
+            let (node_index, ancestors) = path.split_last().expect("path is not empty");
+            for index in path.iter() {
+                self.nodes[*index].size += 1;
+            }
+            let depth = path.len();
+            let node = &mut self.nodes[*node_index];
+            if node.is_leaf() && node.is_dead() {
+                self.slots[entry.id].node = Some(*node_index);
+                node.point = entry.point;
+                node.entry = Some(entry);
+                self.dead_len -= 1;
+            } else if node.is_leaf() && node.size - node.duplicates.len() <= self.bucket_size {
+                self.slots[entry.id].node = Some(*node_index);
+                node.bucket.push(entry);
+            } else if node.is_leaf() && self.bucket_size > 1 {
+                let split = node.split;
+                let mut entries = vec![entry];
+                self.pop_nodes(*node_index, &mut entries);
+                let subtree_index = self.build(entries, split);
+                self.link_subtree(*node_index, ancestors.last().copied(), subtree_index);
+                if let Some(subtree_index) = subtree_index
+                    && depth > self.max_depth()
+                {
+                    self.rebuild_scapegoat(ancestors, subtree_index);
                 }
-                Split::Y => {
-                    lines.push(geometry::Line::PointToPoint(
-                        Point::new(node.point.x, right.point.y),
-                        Point::new(bounds.width, right.point.y),
-                    ));
-                    let bounds = Rectangle {
-                        x: node.point.x,
-                        ..bounds
-                    };
-                    self.dfs_lines(index, lines, bounds);
+            } else {
+                let direction = node.direction(&entry.point);
+                let split = next_split::<P>(node.split);
+                let next_index = self.insert_node(Node {
+                    point: entry.point,
+                    entry: Some(entry),
+                    duplicates: Vec::new(),
+                    bucket: Vec::new(),
+                    left: None,
+                    right: None,
+                    split,
+                    size: 1,
+                });
+                let node = &mut self.nodes[*node_index];
+                if direction {
+                    node.left = Some(next_index)
+                } else {
+                    node.right = Some(next_index)
+                };
+
+                if depth > self.max_depth() {
+                    self.rebuild_scapegoat(&path, next_index);
                 }
             }
         }
+        self.max_size = self.max_size.max(self.len());
     }
 
-    /// Returns the lines drew by `Geometry`
-    pub fn lines(&self) -> Vec<geometry::Line> {
-        if let Some(root) = self.nodes.get(&self.root_index) {
-            let mut lines = Vec::new();
-            lines.push(geometry::Line::Vertical(root.point.x));
-            self.dfs_lines(
-                self.root_index,
-                &mut lines,
-                Rectangle {
-                    x: 0.,
-                    y: 0.,
-                    width: 1.,
-                    height: 1.,
-                },
-            );
-            lines
-        } else {
-            Vec::new()
+    /// Returns the maximum depth allowed for a node before rebalancing, see [`max_depth`]. Each
+    /// leaf holds up to `bucket_size` points, so the depth is bounded by the number of leaves
+    /// which would be needed to hold the points.
+    fn max_depth(&self) -> usize {
+        max_depth(self.len().div_ceil(self.bucket_size))
+    }
+
+    /// Rebuilds the subtree starting from the scapegoat, which is the deepest node of the `path`
+    /// (from the root to the parent of `node_index`) where a child holds more than $\alpha$ times
+    /// its size.
+    fn rebuild_scapegoat(&mut self, path: &[usize], node_index: usize) {
+        let mut child_index = node_index;
+        for (depth, index) in path.iter().enumerate().rev() {
+            let node = &self.nodes[*index];
+            if self.nodes[child_index].size as f32 > ALPHA * node.size as f32 {
+                let parent_index = depth.checked_sub(1).map(|depth| path[depth]);
+                self.rebuild(*index, parent_index, false);
+                return;
+            }
+            child_index = *index;
         }
     }
 
-    /// Returns the points of the tree
-    pub fn points(&self) -> Vec<Point> {
-        self.nodes.values().map(|node| node.point).collect()
+    /// Rebuilds a balanced subtree from the points of the subtree starting from `node_index` and
+    /// links it to its parent. If `remove_root` is `true`, the point of `node_index` is dropped
+    /// and returned with its value.
+    fn rebuild(
+        &mut self,
+        node_index: usize,
+        parent_index: Option<usize>,
+        remove_root: bool,
+    ) -> Option<Entry<P, V>> {
+        #[cfg(test)]
+        tests::REBUILDS.with(|rebuilds| rebuilds.set(rebuilds.get() + 1));
+        let split = self.nodes[node_index].split;
+        let mut entries = Vec::new();
+        self.pop_nodes(node_index, &mut entries);
+        let removed = if remove_root {
+            Some(entries.swap_remove(0))
+        } else {
+            None
+        };
+        let subtree_index = self.build(entries, split);
+        self.link_subtree(node_index, parent_index, subtree_index);
+        removed
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Replaces the child `node_index` of `parent_index` (or the root if there is no parent) by
+    /// the subtree starting from `subtree_index`.
+    fn link_subtree(
+        &mut self,
+        node_index: usize,
+        parent_index: Option<usize>,
+        subtree_index: Option<usize>,
+    ) {
+        match parent_index {
+            Some(parent_index) => {
+                let node = &mut self.nodes[parent_index];
+                if Some(node_index) == node.left {
+                    node.left = subtree_index;
+                } else {
+                    node.right = subtree_index;
+                }
+            }
+            None => {
+                if let Some(subtree_index) = subtree_index {
+                    self.root_index = subtree_index;
+                }
+            }
+        }
+    }
+
+    /// Removes a point by replacement: the point of a node with children is replaced by the
+    /// point of its left subtree with the greatest coordinate along its split axis (or of its
+    /// right subtree, which then becomes the left one), and the replacement is removed
+    /// recursively. Finding a replacement takes $O(n^{1 - 1/K})$ on a balanced tree, see
+    /// [`KDTree::find_min`], and no subtree is rebuilt. The whole tree is rebuilt when its size
+    /// falls below $\alpha$ times the maximum size it reached since its last full rebuild. With
+    /// lazy deletion, the node is marked as dead instead, see [`KDTree::with_lazy_deletion`].
+    ///
+    /// Returns the value associated with the removed point, if any. If several points are equal
+    /// to `point`, which requires [`DuplicatePolicy::Allow`], only the first one found is
+    /// removed; use [`KDTree::remove`] to remove a specific one or [`KDTree::remove_all`] to
+    /// remove all of them.
+    pub fn remove_point(&mut self, point: P) -> Option<V> {
+        let (path, id) = self.find_point_path(&point)?;
+        self.remove_entry(&path, id).map(|(_, value)| value)
+    }
+
+    /// Removes a point like [`KDTree::remove_point`] and returns it with its value, or fails with
+    /// [`KDTreeError::NotFound`] if no point is equal to `point`, or with
+    /// [`KDTreeError::NonFiniteCoordinate`] if a coordinate of `point` is NaN or infinite.
+    pub fn try_remove_point(&mut self, point: P) -> Result<(P, V), KDTreeError> {
+        check_finite(&point)?;
+        let (path, id) = self.find_point_path(&point).ok_or(KDTreeError::NotFound)?;
+        self.remove_entry(&path, id).ok_or(KDTreeError::NotFound)
+    }
+
+    /// Removes all points equal to `point` and returns their values. The points grouped into a
+    /// node are dropped at once, before the point of the node is removed. See
+    /// [`KDTree::remove_point`].
+    pub fn remove_all(&mut self, point: P) -> Vec<V> {
+        let mut values = Vec::new();
+        while let Some((path, id)) = self.find_point_path(&point) {
+            let node = &mut self.nodes[path[path.len() - 1]];
+            if node.entry.as_ref().is_some_and(|entry| entry.id == id) {
+                let duplicates = std::mem::take(&mut node.duplicates);
+                for index in path.iter() {
+                    self.nodes[*index].size -= duplicates.len();
+                }
+                for entry in duplicates {
+                    self.release_slot(entry.id);
+                    values.push(entry.value);
+                }
+            }
+            values.extend(self.remove_entry(&path, id).map(|(_, value)| value));
+        }
+        values
+    }
+
+    /// Removes a batch of points and returns their values in the same order, or `None` for the
+    /// points which do not belong to the tree. Points of the batch equal to each other remove as
+    /// many distinct points of the tree.
+    ///
+    /// The removed points are first searched, then the affected subtrees are visited once from
+    /// the root: a subtree losing more than $1 - \alpha$ of its points is rebuilt balanced
+    /// without them, and the other points are removed like with [`KDTree::remove_point`] once
+    /// their subtrees have been processed. Whether the whole tree is rebuilt or compacted is
+    /// decided once for the whole batch.
+    pub fn remove_many(&mut self, points: &[P]) -> Vec<Option<V>> {
+        let mut values: Vec<Option<V>> = points.iter().map(|_| None).collect();
+        if self.nodes.is_empty() {
+            return values;
+        }
+        // Position in `points` of each removed entry, by handle slot, and number of removed
+        // entries in the subtree of each node
+        let mut positions = vec![None; self.slots.len()];
+        let mut counts = vec![0; self.nodes.len() + self.nodes.free_len()];
+        let mut batch: Vec<(usize, P)> = points.iter().copied().enumerate().collect();
+        self.mark_subtree(self.root_index, &mut batch, &mut positions, &mut counts);
+        if counts[self.root_index] == 0 {
+            return values;
+        }
+
+        let mut removed = Vec::new();
+        self.remove_marked(
+            &mut vec![self.root_index],
+            &positions,
+            &counts,
+            &mut removed,
+        );
+        for entry in removed {
+            self.release_slot(entry.id);
+            if let Some(position) = positions[entry.id] {
+                values[position] = Some(entry.value);
+            }
+        }
+        self.shrink();
+        values
+    }
+
+    /// Marks the entries of the subtree starting from `node_index` equal to the points of
+    /// `batch` with the positions of the points, and counts them into `counts`. Each point marks
+    /// at most one entry, and the batch is partitioned in place between the children. The
+    /// entries grouped into a node are marked in turn without being scanned. Returns the number
+    /// of entries marked in the subtree.
+    fn mark_subtree(
+        &self,
+        node_index: usize,
+        batch: &mut [(usize, P)],
+        positions: &mut [Option<usize>],
+        counts: &mut [usize],
+    ) -> usize {
+        let node = &self.nodes[node_index];
+        let mut group = node.entry.iter().chain(node.duplicates.iter());
+        let mut marked = 0;
+        for index in 0..batch.len() {
+            let (position, point) = batch[index];
+            let entry = if point == node.point {
+                group.next()
+            } else {
+                None
+            };
+            if let Some(entry) = entry.or_else(|| {
+                node.bucket
+                    .iter()
+                    .find(|entry| entry.point == point && positions[entry.id].is_none())
+            }) {
+                positions[entry.id] = Some(position);
+                batch.swap(index, marked);
+                marked += 1;
+            }
+        }
+        let batch = &mut batch[marked..];
+        let mut left_len = 0;
+        for index in 0..batch.len() {
+            if node.direction(&batch[index].1) {
+                batch.swap(index, left_len);
+                left_len += 1;
+            }
+        }
+        let (left_batch, right_batch) = batch.split_at_mut(left_len);
+        let mut count = marked;
+        for (child, batch) in [(node.left, left_batch), (node.right, right_batch)] {
+            if let Some(child_index) = child
+                && !batch.is_empty()
+            {
+                count += self.mark_subtree(child_index, batch, positions, counts);
+            }
+        }
+        counts[node_index] = count;
+        count
+    }
+
+    /// Removes the marked entries, given by their handle slots, from the subtree starting from
+    /// the last node of `path` (from the root to the node) and stores them into `removed`. The
+    /// sizes of the ancestors are not updated. See [`KDTree::remove_many`].
+    fn remove_marked(
+        &mut self,
+        path: &mut Vec<usize>,
+        positions: &[Option<usize>],
+        counts: &[usize],
+        removed: &mut Vec<Entry<P, V>>,
+    ) {
+        let node_index = *path.last().expect("path is not empty");
+        let count = counts.get(node_index).copied().unwrap_or(0);
+        if count == 0 {
+            return;
+        }
+        let node = &self.nodes[node_index];
+        if node.is_leaf() || count as f32 > (1. - ALPHA) * node.size as f32 {
+            let parent_index = path.len().checked_sub(2).map(|depth| path[depth]);
+            let split = node.split;
+            let mut entries = Vec::new();
+            self.pop_nodes(node_index, &mut entries);
+            let (marked_entries, entries): (Vec<_>, Vec<_>) = entries
+                .into_iter()
+                .partition(|entry| positions[entry.id].is_some());
+            removed.extend(marked_entries);
+            let subtree_index = self.build(entries, split);
+            self.link_subtree(node_index, parent_index, subtree_index);
+            return;
+        }
+
+        for child in [node.left, node.right].into_iter().flatten() {
+            path.push(child);
+            self.remove_marked(path, positions, counts, removed);
+            path.pop();
+        }
+        let node = &mut self.nodes[node_index];
+        let (marked_duplicates, duplicates) = std::mem::take(&mut node.duplicates)
+            .into_iter()
+            .partition(|entry| positions[entry.id].is_some());
+        node.duplicates = duplicates;
+        removed.extend(marked_duplicates);
+        let Some(id) = node
+            .entry
+            .as_ref()
+            .map(|entry| entry.id)
+            .filter(|id| positions[*id].is_some())
+        else {
+            node.size -= count;
+            return;
+        };
+        node.size -= count - 1;
+        if self.max_dead_fraction.is_some()
+            && !node.is_leaf()
+            && node.duplicates.is_empty()
+            && node.size > 1
+        {
+            removed.extend(node.entry.take());
+            node.size -= 1;
+            self.dead_len += 1;
+        } else {
+            removed.extend(self.replace_entry(path, id));
+        }
+    }
+
+    /// Returns the number of points equal to `point`, which is at most `1` unless the duplicate
+    /// policy is [`DuplicatePolicy::Allow`]. Equal points all lie on the search path of
+    /// `point`, so only its nodes are scanned, and the points grouped into a node are counted
+    /// without being scanned.
+    pub fn count(&self, point: &P) -> usize {
+        let mut count = 0;
+        let mut node_index = Some(self.root_index).filter(|_| !self.nodes.is_empty());
+        while let Some(index) = node_index {
+            let node = &self.nodes[index];
+            if node
+                .entry
+                .as_ref()
+                .is_some_and(|entry| entry.point == *point)
+            {
+                count += 1 + node.duplicates.len();
+            }
+            count += node
+                .bucket
+                .iter()
+                .filter(|entry| entry.point == *point)
+                .count();
+            node_index = self.single_search(point, index);
+        }
+        count
+    }
+
+    /// Removes the point of the specified handle and returns it with its value. Returns `None` if
+    /// the handle is no longer valid. See [`KDTree::remove_point`].
+    pub fn remove(&mut self, id: PointId) -> Option<(P, V)> {
+        let node_index = self.node_index(id)?;
+        let path = self.find_node_path(node_index);
+        self.remove_entry(&path, id.index)
+    }
+
+    /// Removes the point of the specified handle like [`KDTree::remove`], or fails with
+    /// [`KDTreeError::NotFound`] if the handle is no longer valid.
+    pub fn try_remove(&mut self, id: PointId) -> Result<(P, V), KDTreeError> {
+        self.remove(id).ok_or(KDTreeError::NotFound)
+    }
+
+    /// Removes the entry whose handle slot is `id` from the last node of `path` (from the root to
+    /// the node) and returns its point with its value. The handle of the point becomes invalid.
+    fn remove_entry(&mut self, path: &[usize], id: usize) -> Option<(P, V)> {
+        let entry = self.take_entry(path, id)?;
+        self.release_slot(entry.id);
+        Some((entry.point, entry.value))
+    }
+
+    /// Removes the entry whose handle slot is `id` from the last node of `path` and returns it
+    /// without releasing its handle slot. The node is marked as dead with lazy deletion unless
+    /// other points are grouped into it, otherwise the entry is replaced, see
+    /// [`KDTree::replace_entry`].
+    fn take_entry(&mut self, path: &[usize], id: usize) -> Option<Entry<P, V>> {
+        let (node_index, ancestors) = path.split_last()?;
+        let node = &mut self.nodes[*node_index];
+        let removed = if self.max_dead_fraction.is_some()
+            && !node.is_leaf()
+            && node.duplicates.is_empty()
+            && node.size > 1
+        {
+            let entry = node.entry.take_if(|entry| entry.id == id)?;
+            node.size -= 1;
+            self.dead_len += 1;
+            Some(entry)
+        } else {
+            self.replace_entry(path, id)
+        }?;
+        for index in ancestors.iter() {
+            self.nodes[*index].size -= 1;
+        }
+        self.shrink();
+        Some(removed)
+    }
+
+    /// Rebuilds the whole tree after removals when it holds too many dead nodes with lazy
+    /// deletion, or when its size fell below $\alpha$ times its maximum size otherwise, and
+    /// compacts the nodes when the arena holds more free slots than nodes.
+    fn shrink(&mut self) {
+        let rebuild = match self.max_dead_fraction {
+            Some(max_dead_fraction) => {
+                self.dead_len as f32 > max_dead_fraction * (self.dead_len + self.len()) as f32
+            }
+            None => (self.len() as f32) < ALPHA * self.max_size as f32,
+        };
+        if rebuild {
+            if !self.nodes.is_empty() {
+                self.rebuild(self.root_index, None, false);
+            }
+            self.max_size = self.len();
+        }
+        if self.nodes.free_len() > self.nodes.len() {
+            self.compact();
+        }
+    }
+
+    /// Removes the entry whose handle slot is `id` from the last node of `path` (from the root to
+    /// the node) and updates the sizes of the subtree, but not of the ancestors. An entry grouped
+    /// into the node or held by its bucket is dropped, and the entry of the node is replaced by
+    /// one of these entries if any. A leaf without other points is dropped. Otherwise, the entry
+    /// of the node is replaced by the entry of its left subtree with the greatest coordinate
+    /// along its split axis, which is removed recursively along with the entries grouped into
+    /// it, so that the points of the left subtree stay lower than or equal to the split plane.
+    /// When the left subtree holds no points, the replacement is taken from the right subtree
+    /// which becomes the left one.
+    fn replace_entry(&mut self, path: &[usize], id: usize) -> Option<Entry<P, V>> {
+        let (node_index, ancestors) = path.split_last()?;
+        let parent_index = ancestors.last().copied();
+        let node = &mut self.nodes[*node_index];
+        for entries in [&mut node.duplicates, &mut node.bucket] {
+            if let Some(position) = entries.iter().position(|entry| entry.id == id) {
+                node.size -= 1;
+                return Some(entries.swap_remove(position));
+            }
+        }
+        node.entry.as_ref().filter(|entry| entry.id == id)?;
+        if let Some(last) = node.duplicates.pop().or_else(|| node.bucket.pop()) {
+            node.size -= 1;
+            node.point = last.point;
+            return node.entry.replace(last);
+        }
+        if node.is_leaf() {
+            let node = self.nodes.remove(*node_index)?;
+            self.link_subtree(*node_index, parent_index, None);
+            return node.entry;
+        }
+
+        let (left, right, split) = (node.left, node.right, node.split);
+        let replacement =
+            [(left, false), (right, true)]
+                .into_iter()
+                .find_map(|(child, from_right)| {
+                    let (holder_index, entry) = self.extreme_entry(child?, split, true)?;
+                    Some((holder_index, entry.id, from_right))
+                });
+        let Some((holder_index, holder_id, from_right)) = replacement else {
+            // Only dead nodes are left below the node
+            return self.rebuild(*node_index, parent_index, true);
+        };
+        let holder_path = self.find_node_path(holder_index);
+        let holder = &mut self.nodes[holder_index];
+        let duplicates = match holder.entry.as_ref() {
+            Some(entry) if entry.id == holder_id => std::mem::take(&mut holder.duplicates),
+            _ => Vec::new(),
+        };
+        for index in holder_path[path.len()..].iter() {
+            self.nodes[*index].size -= duplicates.len();
+        }
+        let replacement = self.replace_entry(&holder_path, holder_id)?;
+        for index in holder_path[path.len()..holder_path.len() - 1].iter() {
+            self.nodes[*index].size -= 1;
+        }
+        if from_right && let Some(left_index) = left {
+            self.pop_nodes(left_index, &mut Vec::new());
+        }
+
+        let node = &mut self.nodes[*node_index];
+        node.size -= 1;
+        node.point = replacement.point;
+        node.duplicates = duplicates;
+        if from_right {
+            node.left = node.right.take();
+        }
+        let removed = node.entry.replace(replacement);
+        self.link_slots(*node_index);
+        removed
+    }
+
+    /// Relocates the nodes into a new arena without free slots in $O(n)$. The nodes are stored in
+    /// depth-first order, so that each node is followed by its left subtree. The shape of the
+    /// tree and the point handles are kept.
+    ///
+    /// The tree is compacted automatically when removals leave more free slots than nodes.
+    pub fn compact(&mut self) {
+        let mut nodes = Arena::with_capacity(self.nodes.len());
+        if !self.nodes.is_empty() {
+            self.root_index = self.relocate(self.root_index, &mut nodes);
+        }
+        self.nodes = nodes;
+    }
+
+    /// Moves the nodes of the subtree starting from `node_index` into `nodes` and returns the new
+    /// index of the node.
+    fn relocate(&mut self, node_index: usize, nodes: &mut Arena<Node<P, V>>) -> usize {
+        let node = self
+            .nodes
+            .remove(node_index)
+            .expect("node belongs to the tree");
+        let (left, right) = (node.left, node.right);
+        let index = nodes.insert(node);
+        for entry in nodes[index].entries() {
+            self.slots[entry.id].node = Some(index);
+        }
+        let left = left.map(|left_index| self.relocate(left_index, nodes));
+        let right = right.map(|right_index| self.relocate(right_index, nodes));
+        let node = &mut nodes[index];
+        node.left = left;
+        node.right = right;
+        index
+    }
+
+    /// Moves a point from `old` to `new` position, keeping its value and its handle. Returns
+    /// `false` if `old` does not belong to the tree or if the move is rejected by the duplicate
+    /// policy. See [`KDTree::move_to`].
+    pub fn update_point(&mut self, old: P, new: P) -> bool {
+        match self.find_point_path(&old) {
+            Some((path, id)) => self.move_entry(&path, id, new),
+            None => false,
+        }
+    }
+
+    /// Moves the point of the specified handle to the `new` position, keeping its value and its
+    /// handle. Returns `false` if the handle is no longer valid.
+    ///
+    /// When another point is equal to `new`, the [`DuplicatePolicy`] of the tree applies: with
+    /// [`DuplicatePolicy::Reject`] the point is not moved and `false` is returned, and with
+    /// [`DuplicatePolicy::Replace`] the other point is removed.
+    ///
+    /// The point is updated in place in $O(\log_2(n))$ when the new position stays inside the cell
+    /// of its node, i.e. on the same side of the split planes of all its ancestors and between
+    /// the points of its left and right subtrees along its own split axis, unless other points
+    /// are grouped into the node. Otherwise, the point is removed and inserted again.
+    pub fn move_to(&mut self, id: PointId, new: P) -> bool {
+        match self.node_index(id) {
+            Some(node_index) => {
+                let path = self.find_node_path(node_index);
+                self.move_entry(&path, id.index, new)
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the point of the specified handle like [`KDTree::move_to`], or fails with:
+    ///
+    /// - [`KDTreeError::NotFound`] if the handle is no longer valid,
+    /// - [`KDTreeError::NonFiniteCoordinate`] if a coordinate of `new` is NaN or infinite,
+    /// - [`KDTreeError::Duplicate`] if another point is equal to `new` and is kept by
+    ///   [`DuplicatePolicy::Reject`].
+    pub fn try_move_to(&mut self, id: PointId, new: P) -> Result<(), KDTreeError> {
+        check_finite(&new)?;
+        let node_index = self.node_index(id).ok_or(KDTreeError::NotFound)?;
+        if self.duplicate_policy == DuplicatePolicy::Reject
+            && let Some((path, other)) = self.find_point_path_except(&new, Some(id.index))
+        {
+            let node = &self.nodes[path[path.len() - 1]];
+            let entry = node.find_entry(other).expect("found entry");
+            return Err(KDTreeError::Duplicate(self.point_id(entry)));
+        }
+        let path = self.find_node_path(node_index);
+        self.move_entry(&path, id.index, new);
+        Ok(())
+    }
+
+    /// Moves the point of the entry whose handle slot is `id` in the last node of `path` (from
+    /// the root to the node) to `new`. Returns `false` if the entry is not found or if another
+    /// point equal to `new` is kept by the duplicate policy.
+    fn move_entry(&mut self, path: &[usize], id: usize, new: P) -> bool {
+        if self.duplicate_policy != DuplicatePolicy::Allow
+            && let Some((other_path, other)) = self.find_point_path_except(&new, Some(id))
+        {
+            if self.duplicate_policy == DuplicatePolicy::Reject {
+                return false;
+            }
+            // The removal may restructure the tree, so the path of the entry is searched again
+            self.remove_entry(&other_path, other);
+            let Some(node_index) = self.slots[id].node else {
+                return false;
+            };
+            let path = self.find_node_path(node_index);
+            return self.move_entry(&path, id, new);
+        }
+
+        let (node_index, ancestors) = path.split_last().expect("path is not empty");
+        let Some(old) = self.nodes[*node_index]
+            .find_entry(id)
+            .map(|entry| entry.point)
+        else {
+            return false;
+        };
+        let in_cell = self.nodes[*node_index].duplicates.is_empty()
+            && ancestors.iter().all(|index| {
+                let ancestor = &self.nodes[*index];
+                ancestor.direction(&old) == ancestor.direction(&new)
+            })
+            && self.is_between_children(*node_index, &new);
+
+        if in_cell {
+            let node = &mut self.nodes[*node_index];
+            if let Some(entry) = node.find_entry_mut(id) {
+                entry.point = new;
+            }
+            if node.entry.as_ref().is_some_and(|entry| entry.id == id) {
+                node.point = new;
+            }
+        } else if let Some(entry) = self.take_entry(path, id) {
+            self.insert_entry(Entry {
+                point: new,
+                ..entry
+            });
+        }
+        true
+    }
+
+    /// Checks if the node at `node_index` can hold `point` without breaking the order of its
+    /// children: all points of the left subtree must stay lower than or equal to `point` and all
+    /// points of the right subtree must stay strictly greater, along the split axis. Any point
+    /// of the cell can be held by a leaf.
+    fn is_between_children(&self, node_index: usize, point: &P) -> bool {
+        let node = &self.nodes[node_index];
+        let value = point.coord(node.split);
+        let current = node.point.coord(node.split);
+        if value < current {
+            node.left
+                .is_none_or(|index| self.subtree_max(index, node.split) <= value)
+        } else if value > current {
+            node.right
+                .is_none_or(|index| self.subtree_min(index, node.split) > value)
+        } else {
+            true
+        }
+    }
+
+    /// Returns the minimum coordinate along `axis` of the points of the subtree starting from
+    /// `node_index`. Right subtrees of nodes split along `axis` are skipped since their points
+    /// are greater.
+    fn subtree_min(&self, node_index: usize, axis: usize) -> P::Scalar {
+        let node = &self.nodes[node_index];
+        let mut min = node.point.coord(axis);
+        for entry in node.bucket.iter() {
+            let value = entry.point.coord(axis);
+            if value < min {
+                min = value;
+            }
+        }
+        let children = [node.left, node.right.filter(|_| node.split != axis)];
+        for index in children.into_iter().flatten() {
+            let value = self.subtree_min(index, axis);
+            if value < min {
+                min = value;
+            }
+        }
+        min
+    }
+
+    /// Returns the maximum coordinate along `axis` of the points of the subtree starting from
+    /// `node_index`. Left subtrees of nodes split along `axis` are skipped since their points are
+    /// lower or equal.
+    fn subtree_max(&self, node_index: usize, axis: usize) -> P::Scalar {
+        let node = &self.nodes[node_index];
+        let mut max = node.point.coord(axis);
+        for entry in node.bucket.iter() {
+            let value = entry.point.coord(axis);
+            if value > max {
+                max = value;
+            }
+        }
+        let children = [node.left.filter(|_| node.split != axis), node.right];
+        for index in children.into_iter().flatten() {
+            let value = self.subtree_max(index, axis);
+            if value > max {
+                max = value;
+            }
+        }
+        max
+    }
+
+    /// Finds the point with the lowest coordinate along `axis` and returns it with its value.
+    /// Only the left subtrees of the nodes split along `axis` are searched, which takes
+    /// $O(n^{1 - 1/K})$ on a balanced tree.
+    pub fn find_min(&self, axis: usize) -> Option<(P, &V)> {
+        self.nodes.get(self.root_index)?;
+        self.extreme_entry(self.root_index, axis, false)
+            .map(|(_, entry)| entry.pair())
+    }
+
+    /// Finds the point with the greatest coordinate along `axis` and returns it with its value.
+    /// See [`KDTree::find_min`].
+    pub fn find_max(&self, axis: usize) -> Option<(P, &V)> {
+        self.nodes.get(self.root_index)?;
+        self.extreme_entry(self.root_index, axis, true)
+            .map(|(_, entry)| entry.pair())
+    }
+
+    /// Returns the entry with the lowest coordinate along `axis` (the greatest if `max` is
+    /// `true`) in the subtree starting from `node_index`, with the index of the node holding it.
+    /// Dead nodes are skipped. The right subtree of a node split along `axis` (the left subtree
+    /// if `max` is `true`) is only searched when the node and its other subtree hold no points.
+    fn extreme_entry(
+        &self,
+        node_index: usize,
+        axis: usize,
+        max: bool,
+    ) -> Option<(usize, &Entry<P, V>)> {
+        let is_better = |entry: &Entry<P, V>, best: &Option<(usize, &Entry<P, V>)>| {
+            best.is_none_or(|(_, best)| {
+                let (value, best) = (entry.point.coord(axis), best.point.coord(axis));
+                if max { value > best } else { value < best }
+            })
+        };
+        let node = &self.nodes[node_index];
+        let mut best = None;
+        for entry in node.entries() {
+            if is_better(entry, &best) {
+                best = Some((node_index, entry));
+            }
+        }
+        let (near, far) = if max {
+            (node.right, node.left)
+        } else {
+            (node.left, node.right)
+        };
+        for (child, is_far) in [(near, false), (far, true)] {
+            if let Some(child_index) = child
+                && !(is_far && node.split == axis && best.is_some())
+                && let Some((holder_index, entry)) = self.extreme_entry(child_index, axis, max)
+                && is_better(entry, &best)
+            {
+                best = Some((holder_index, entry));
+            }
+        }
+        best
+    }
+
+    /// Returns the point of the specified handle with its value, or `None` if the handle is no
+    /// longer valid.
+    pub fn get(&self, id: PointId) -> Option<(P, &V)> {
+        let index = self.node_index(id)?;
+        self.nodes[index].find_entry(id.index).map(Entry::pair)
+    }
+
+    /// Returns a mutable reference to the value of the point of the specified handle, or `None`
+    /// if the handle is no longer valid.
+    pub fn get_mut(&mut self, id: PointId) -> Option<&mut V> {
+        let index = self.node_index(id)?;
+        self.nodes
+            .get_mut(index)?
+            .find_entry_mut(id.index)
+            .map(|entry| &mut entry.value)
+    }
+
+    /// Returns a reference to the value associated with the specified point.
+    pub fn get_by_point(&self, point: &P) -> Option<&V> {
+        let (path, id) = self.find_point_path(point)?;
+        let entry = self.nodes[*path.last()?].find_entry(id)?;
+        Some(&entry.value)
+    }
+
+    /// Returns a mutable reference to the value associated with the specified point.
+    pub fn get_by_point_mut(&mut self, point: &P) -> Option<&mut V> {
+        let (path, id) = self.find_point_path(point)?;
+        self.nodes[*path.last()?]
+            .find_entry_mut(id)
+            .map(|entry| &mut entry.value)
+    }
+
+    /// Returns the indices of the nodes from the root to the node at `node_index`, which must
+    /// belong to the tree.
+    fn find_node_path(&self, node_index: usize) -> Vec<usize> {
+        let point = &self.nodes[node_index].point;
+        let mut path = vec![self.root_index];
+        while path[path.len() - 1] != node_index {
+            match self.single_search(point, path[path.len() - 1]) {
+                Some(index) => path.push(index),
+                None => break,
+            }
+        }
+        path
+    }
+
+    /// Returns the indices of the nodes from the root to the node holding the specified point,
+    /// with the handle slot of its entry. The point must belong to the tree's points because the
+    /// points of the entries are compared with `point`.
+    fn find_point_path(&self, point: &P) -> Option<(Vec<usize>, usize)> {
+        self.find_point_path_except(point, None)
+    }
+
+    /// Same as [`KDTree::find_point_path`] but skips the entry whose handle slot is `except`.
+    fn find_point_path_except(
+        &self,
+        point: &P,
+        except: Option<usize>,
+    ) -> Option<(Vec<usize>, usize)> {
+        let mut path = Vec::new();
+        let mut node_index = Some(self.root_index).filter(|_| !self.nodes.is_empty());
+        while let Some(index) = node_index {
+            path.push(index);
+            if let Some(entry) = self.nodes[index]
+                .entries()
+                .find(|entry| entry.point == *point && Some(entry.id) != except)
+            {
+                return Some((path, entry.id));
+            }
+            node_index = self.single_search(point, index);
+        }
+        None
+    }
+
+    /// Removes all nodes starting from `node_index` and store their points and values into
+    /// `entries`. The entry of the split plane of `node_index` is stored first, and dead nodes
+    /// are dropped.
+    fn pop_nodes(&mut self, node_index: usize, entries: &mut Vec<Entry<P, V>>) {
+        if let Some(node) = self.nodes.remove(node_index) {
+            match node.entry {
+                Some(entry) => entries.push(entry),
+                None => self.dead_len -= 1,
+            }
+            entries.extend(node.duplicates);
+            entries.extend(node.bucket);
+            if let Some(left_index) = node.left {
+                self.pop_nodes(left_index, entries);
+            }
+            if let Some(right_index) = node.right {
+                self.pop_nodes(right_index, entries);
+            }
+        }
+    }
+
+    /// Returns the indices of the nodes from the root to the depthest node of the tree given the
+    /// specified `point`. The tree must not be empty.
+    fn find_path(&self, point: &P) -> Vec<usize> {
+        let mut path = vec![self.root_index];
+        while let Some(index) = self.single_search(point, path[path.len() - 1]) {
+            path.push(index);
+        }
+        path
+    }
+
+    /// Returns the next node to traverse given a specified `point` and the current `node_index`.
+    fn single_search(&self, point: &P, node_index: usize) -> Option<usize> {
+        let node = &self.nodes[node_index];
+        if node.direction(point) {
+            node.left
+        } else {
+            node.right
+        }
+    }
+
+    /// Returns a view of the tree whose queries measure distances with the specified `metric`
+    /// instead of the [`Euclidean`] distance.
+    pub fn using<M: Metric<P>>(&self, metric: M) -> MetricView<'_, P, V, M> {
+        MetricView { tree: self, metric }
+    }
+
+    /// Finds the nearest neighbor of the specified `point` and returns it with its value.
+    pub fn nearest_neighbor(&self, point: &P) -> Option<(P, &V)> {
+        self.using(Euclidean).nearest_neighbor(point)
+    }
+
+    /// Same as [`KDTree::nearest_neighbor`] but returns the handle of the nearest neighbor.
+    pub fn nearest_neighbor_id(&self, point: &P) -> Option<PointId> {
+        self.using(Euclidean).nearest_neighbor_id(point)
+    }
+
+    /// Finds the `k` nearest neighbors of the specified `point`, sorted by increasing distance.
+    /// Each point is returned with its value and its distance to `point`. Fewer than `k` points
+    /// are returned when the tree holds less than `k` points.
+    pub fn k_nearest_neighbors(&self, point: &P, k: usize) -> Vec<(P, &V, Real<P>)> {
+        self.using(Euclidean).k_nearest_neighbors(point, k)
+    }
+
+    /// Same as [`KDTree::k_nearest_neighbors`] but returns the handles of the neighbors.
+    pub fn k_nearest_neighbor_ids(&self, point: &P, k: usize) -> Vec<(PointId, Real<P>)> {
+        self.using(Euclidean).k_nearest_neighbor_ids(point, k)
+    }
+
+    /// Finds the nearest neighbor of the specified `point` among the points accepted by
+    /// `filter`. See [`MetricView::nearest_neighbor_where`].
+    pub fn nearest_neighbor_where(
+        &self,
+        point: &P,
+        filter: impl FnMut(&P, &V) -> bool,
+    ) -> Option<(P, &V)> {
+        self.using(Euclidean).nearest_neighbor_where(point, filter)
+    }
+
+    /// Finds the `k` nearest neighbors of the specified `point` among the points accepted by
+    /// `filter`. See [`MetricView::k_nearest_neighbors_where`].
+    pub fn k_nearest_neighbors_where(
+        &self,
+        point: &P,
+        k: usize,
+        filter: impl FnMut(&P, &V) -> bool,
+    ) -> Vec<(P, &V, Real<P>)> {
+        self.using(Euclidean)
+            .k_nearest_neighbors_where(point, k, filter)
+    }
+
+    /// Finds the farthest point from the specified `point` and returns it with its value.
+    pub fn farthest_point(&self, point: &P) -> Option<(P, &V)> {
+        self.using(Euclidean).farthest_point(point)
+    }
+
+    /// Finds the `k` farthest points from the specified `point`, sorted by decreasing distance.
+    /// See [`MetricView::k_farthest_points`].
+    pub fn k_farthest_points(&self, point: &P, k: usize) -> Vec<(P, &V, Real<P>)> {
+        self.using(Euclidean).k_farthest_points(point, k)
+    }
+
+    /// Finds all points whose distance to the specified `point` is lower than or equal to
+    /// `radius`. Each point is returned with its value and its distance to `point`, in no
+    /// particular order. A negative or NaN radius holds no points.
+    pub fn within_radius(&self, point: &P, radius: Real<P>) -> Vec<(P, &V, Real<P>)> {
+        self.using(Euclidean).within_radius(point, radius)
+    }
+
+    /// Same as [`KDTree::within_radius`] but the points are sorted by increasing distance.
+    pub fn within_radius_sorted(&self, point: &P, radius: Real<P>) -> Vec<(P, &V, Real<P>)> {
+        self.using(Euclidean).within_radius_sorted(point, radius)
+    }
+
+    /// Same as [`KDTree::within_radius`] but returns the handles of the points.
+    pub fn within_radius_ids(&self, point: &P, radius: Real<P>) -> Vec<(PointId, Real<P>)> {
+        self.using(Euclidean).within_radius_ids(point, radius)
+    }
+
+    /// Finds an approximate nearest neighbor of the specified `point` whose distance is at most
+    /// $1 + \varepsilon$ times the distance of the nearest neighbor. Returns it with its value
+    /// and the number of nodes examined. See [`MetricView::approx_nearest_neighbor`].
+    pub fn approx_nearest_neighbor(&self, point: &P, epsilon: f64) -> Option<(P, &V, usize)> {
+        self.using(Euclidean)
+            .approx_nearest_neighbor(point, epsilon)
+    }
+
+    /// Same as [`KDTree::approx_nearest_neighbor`] but examines at most `max_visited` nodes.
+    /// See [`MetricView::approx_nearest_neighbor_bounded`].
+    pub fn approx_nearest_neighbor_bounded(
+        &self,
+        point: &P,
+        epsilon: f64,
+        max_visited: usize,
+    ) -> Option<(P, &V, usize)> {
+        self.using(Euclidean)
+            .approx_nearest_neighbor_bounded(point, epsilon, max_visited)
+    }
+
+    /// Returns an iterator over the points of the tree by increasing distance to the specified
+    /// `point`. See [`MetricView::neighbors_by_distance`].
+    pub fn neighbors_by_distance(&self, point: &P) -> NeighborsByDistance<'_, P, V, Euclidean> {
+        self.using(Euclidean).neighbors_by_distance(point)
+    }
+
+    /// Searchs the nearest neighbor accepted by `filter` recursively and updates `best` with
+    /// its entry and its distance to `point`. Rejected entries are skipped but the subtrees
+    /// below them are still searched.
+    fn nearest_neighbor_search<'a, M: Metric<P>>(
+        &'a self,
+        point: &P,
+        node_index: usize,
+        metric: &M,
+        filter: &mut impl FnMut(&P, &V) -> bool,
+        best: &mut Option<(&'a Entry<P, V>, M::Distance)>,
+    ) {
+        let node = &self.nodes[node_index];
+        let (primary, secondary) = if node.direction(point) {
+            (node.left, node.right)
+        } else {
+            (node.right, node.left)
+        };
+
+        for entry in node.entries() {
+            let distance = metric.distance(point, &entry.point);
+            if best.is_none_or(|(_, best)| distance < best) && filter(&entry.point, &entry.value) {
+                *best = Some((entry, distance));
+            }
+        }
+
+        if let Some(primary_index) = primary {
+            self.nearest_neighbor_search(point, primary_index, metric, filter, best);
+        }
+
+        if let Some(secondary_index) = secondary
+            && best.is_none_or(|(_, distance)| node.is_in_ball(point, distance, metric))
+        {
+            self.nearest_neighbor_search(point, secondary_index, metric, filter, best);
+        }
+    }
+
+    /// Searchs an approximate nearest neighbor recursively. The secondary child of a node is
+    /// skipped when the distance to its split plane times $1 + \varepsilon$ is not lower than
+    /// the distance of the best candidate, and the search stops once `search.max_visited` nodes
+    /// have been examined.
+    fn approx_nearest_neighbor_search<'a, M: Metric<P>>(
+        &'a self,
+        point: &P,
+        node_index: usize,
+        metric: &M,
+        search: &mut ApproxSearch<'a, P, V, M::Distance, Real<P>>,
+    ) {
+        if search.visited >= search.max_visited {
+            return;
+        }
+        search.visited += 1;
+
+        let node = &self.nodes[node_index];
+        let (primary, secondary) = if node.direction(point) {
+            (node.left, node.right)
+        } else {
+            (node.right, node.left)
+        };
+
+        for entry in node.entries() {
+            let distance = metric.distance(point, &entry.point);
+            if search.best.is_none_or(|(_, best)| distance < best) {
+                search.best = Some((entry, distance));
+                search.bound = Some(match search.factor {
+                    Some(factor) => metric.distance_bound(metric.to_real(distance) / factor),
+                    None => distance,
+                });
+            }
+        }
+
+        if let Some(primary_index) = primary {
+            self.approx_nearest_neighbor_search(point, primary_index, metric, search);
+        }
+
+        if let Some(secondary_index) = secondary
+            && search
+                .bound
+                .is_none_or(|bound| node.is_in_ball(point, bound, metric))
+        {
+            self.approx_nearest_neighbor_search(point, secondary_index, metric, search);
+        }
+    }
+
+    /// Finds the `k` nearest neighbors of `point` accepted by `filter` sorted by increasing
+    /// distance.
+    fn k_nearest_entries<M: Metric<P>>(
+        &self,
+        point: &P,
+        k: usize,
+        metric: &M,
+        mut filter: impl FnMut(&P, &V) -> bool,
+    ) -> Vec<Neighbor<'_, P, V, M::Distance>> {
+        let mut heap = BinaryHeap::with_capacity(k);
+        if k > 0 && !self.nodes.is_empty() {
+            self.k_nearest_neighbors_search(
+                point,
+                k,
+                self.root_index,
+                metric,
+                &mut filter,
+                &mut heap,
+            );
+        }
+        heap.into_sorted_vec()
+    }
+
+    /// Searchs the `k` nearest neighbors accepted by `filter` recursively. `heap` is a max-heap
+    /// holding at most `k` candidates where the top is the farthest one, which gives the radius
+    /// of the ball used for pruning.
+    fn k_nearest_neighbors_search<'a, M: Metric<P>>(
+        &'a self,
+        point: &P,
+        k: usize,
+        node_index: usize,
+        metric: &M,
+        filter: &mut impl FnMut(&P, &V) -> bool,
+        heap: &mut BinaryHeap<Neighbor<'a, P, V, M::Distance>>,
+    ) {
+        let node = &self.nodes[node_index];
+        let (primary, secondary) = if node.direction(point) {
+            (node.left, node.right)
+        } else {
+            (node.right, node.left)
+        };
+
+        if let Some(primary_index) = primary {
+            self.k_nearest_neighbors_search(point, k, primary_index, metric, filter, heap);
+        }
+
+        for entry in node.entries() {
+            let distance = metric.distance(point, &entry.point);
+            if (heap.len() < k
+                || heap
+                    .peek()
+                    .is_some_and(|farthest| distance < farthest.distance))
+                && filter(&entry.point, &entry.value)
+            {
+                if heap.len() == k {
+                    heap.pop();
+                }
+                heap.push(Neighbor { distance, entry });
+            }
+        }
+
+        if let Some(secondary_index) = secondary
+            && (heap.len() < k
+                || heap
+                    .peek()
+                    .is_some_and(|farthest| node.is_in_ball(point, farthest.distance, metric)))
+        {
+            self.k_nearest_neighbors_search(point, k, secondary_index, metric, filter, heap);
+        }
+    }
+
+    /// Finds the `k` farthest points from `point` sorted by decreasing distance.
+    fn k_farthest_entries<M: Metric<P>>(
+        &self,
+        point: &P,
+        k: usize,
+        metric: &M,
+    ) -> Vec<Neighbor<'_, P, V, M::Distance>> {
+        let mut heap = BinaryHeap::with_capacity(k);
+        if k > 0 && !self.nodes.is_empty() {
+            let mut cell = self.bounding_box();
+            self.k_farthest_neighbors_search(
+                point,
+                k,
+                self.root_index,
+                &mut cell,
+                metric,
+                &mut heap,
+            );
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(neighbor)| neighbor)
+            .collect()
+    }
+
+    /// Returns the minimum and maximum coordinates of the points of the tree along each axis.
+    /// Only the subtrees which may hold an extreme coordinate are visited.
+    fn bounding_box(&self) -> Vec<(P::Scalar, P::Scalar)> {
+        (0..P::dim())
+            .map(|axis| {
+                (
+                    self.subtree_min(self.root_index, axis),
+                    self.subtree_max(self.root_index, axis),
+                )
+            })
+            .collect()
+    }
+
+    /// Searchs the `k` farthest points recursively. `heap` is a min-heap holding at most `k`
+    /// candidates where the top is the closest one. `cell` holds the bounds of the points of
+    /// the subtree along each axis, and the subtree is skipped when its corner farthest from
+    /// `point` is not farther than the top of the full heap. The child on the other side of the
+    /// split plane is searched first since it holds the farthest points.
+    fn k_farthest_neighbors_search<'a, M: Metric<P>>(
+        &'a self,
+        point: &P,
+        k: usize,
+        node_index: usize,
+        cell: &mut [(P::Scalar, P::Scalar)],
+        metric: &M,
+        heap: &mut BinaryHeap<Reverse<Neighbor<'a, P, V, M::Distance>>>,
+    ) {
+        if heap.len() == k
+            && let Some(Reverse(closest)) = heap.peek()
+            && cell_max_distance(point, cell, metric) <= closest.distance
+        {
+            return;
+        }
+
+        let node = &self.nodes[node_index];
+        for entry in node.entries() {
+            let distance = metric.distance(point, &entry.point);
+            if heap.len() < k
+                || heap
+                    .peek()
+                    .is_some_and(|Reverse(closest)| distance > closest.distance)
+            {
+                if heap.len() == k {
+                    heap.pop();
+                }
+                heap.push(Reverse(Neighbor { distance, entry }));
+            }
+        }
+
+        let value = node.point.coord(node.split);
+        let (min, max) = cell[node.split];
+        let children = if node.direction(point) {
+            [(node.right, (value, max)), (node.left, (min, value))]
+        } else {
+            [(node.left, (min, value)), (node.right, (value, max))]
+        };
+        for (child, bounds) in children {
+            if let Some(child_index) = child {
+                cell[node.split] = bounds;
+                self.k_farthest_neighbors_search(point, k, child_index, cell, metric, heap);
+            }
+        }
+        cell[node.split] = (min, max);
+    }
+
+    /// Finds the entries of all points within the distance `radius` of `point` with their
+    /// distance to `point`.
+    fn within_distance<M: Metric<P>>(
+        &self,
+        point: &P,
+        radius: M::Distance,
+        metric: &M,
+    ) -> Vec<(&Entry<P, V>, M::Distance)> {
+        let mut neighbors = Vec::new();
+        if !self.nodes.is_empty() {
+            self.within_radius_search(point, radius, self.root_index, metric, &mut neighbors);
+        }
+        neighbors
+    }
+
+    /// Searchs the points in the ball of center `point` recursively. A child node is visited
+    /// only if it is on the same side as `point` or if the split plane intersects the ball.
+    fn within_radius_search<'a, M: Metric<P>>(
+        &'a self,
+        point: &P,
+        radius: M::Distance,
+        node_index: usize,
+        metric: &M,
+        neighbors: &mut Vec<(&'a Entry<P, V>, M::Distance)>,
+    ) {
+        let node = &self.nodes[node_index];
+        let (primary, secondary) = if node.direction(point) {
+            (node.left, node.right)
+        } else {
+            (node.right, node.left)
+        };
+
+        for entry in node.entries() {
+            let distance = metric.distance(point, &entry.point);
+            if distance <= radius {
+                neighbors.push((entry, distance));
+            }
+        }
+
+        if let Some(primary_index) = primary {
+            self.within_radius_search(point, radius, primary_index, metric, neighbors);
+        }
+
+        if let Some(secondary_index) = secondary
+            && node.plane_distance(point, metric) <= radius
+        {
+            self.within_radius_search(point, radius, secondary_index, metric, neighbors);
+        }
+    }
+
+    /// Finds all points inside the axis-aligned box delimited by the `min` and `max` corners,
+    /// borders included. Each point is returned with its value.
+    pub fn range_query_bounds(&self, min: &P, max: &P) -> Vec<(P, &V)> {
+        self.range_entries(min, max)
+            .into_iter()
+            .map(Entry::pair)
+            .collect()
+    }
+
+    /// Same as [`KDTree::range_query_bounds`] but returns the handles of the points.
+    pub fn range_query_ids(&self, min: &P, max: &P) -> Vec<PointId> {
+        self.range_entries(min, max)
+            .into_iter()
+            .map(|entry| self.point_id(entry))
+            .collect()
+    }
+
+    /// Finds the entries of all points inside the box delimited by `min` and `max`.
+    fn range_entries(&self, min: &P, max: &P) -> Vec<&Entry<P, V>> {
+        let mut entries = Vec::new();
+        if !self.nodes.is_empty() {
+            self.range_search(min, max, self.root_index, &mut entries);
+        }
+        entries
+    }
+
+    /// Searchs the entries of the points inside the box delimited by `min` and `max`
+    /// recursively. A child node is visited only if its half-space intersects the box.
+    fn range_search<'a>(
+        &'a self,
+        min: &P,
+        max: &P,
+        node_index: usize,
+        entries: &mut Vec<&'a Entry<P, V>>,
+    ) {
+        let node = &self.nodes[node_index];
+        let value = node.point.coord(node.split);
+
+        for entry in node.entries() {
+            if (0..P::dim())
+                .all(|axis| (min.coord(axis)..=max.coord(axis)).contains(&entry.point.coord(axis)))
+            {
+                entries.push(entry);
+            }
+        }
+
+        if let Some(left_index) = node.left
+            && min.coord(node.split) <= value
+        {
+            self.range_search(min, max, left_index, entries);
+        }
+
+        if let Some(right_index) = node.right
+            && max.coord(node.split) > value
+        {
+            self.range_search(min, max, right_index, entries);
+        }
+    }
+
+    /// Returns the points of the tree
+    pub fn points(&self) -> Vec<P> {
+        self.iter().map(|(point, _)| point).collect()
+    }
+
+    /// Returns an iterator over the points of the tree and their values, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (P, &V)> {
+        self.nodes
+            .values()
+            .flat_map(|node| node.entries().map(Entry::pair))
+    }
+
+    /// Consumes the tree and returns its points with their values
+    pub fn into_entries(self) -> Vec<(P, V)> {
+        self.nodes
+            .into_values()
+            .flat_map(|node| {
+                node.entry
+                    .into_iter()
+                    .chain(node.duplicates)
+                    .chain(node.bucket)
+            })
+            .map(|entry| (entry.point, entry.value))
+            .collect()
+    }
+
+    /// Returns the depth of the tree, i.e. the number of nodes on the longest path from the root
+    /// to a leaf.
+    pub fn depth(&self) -> usize {
+        if self.nodes.is_empty() {
+            0
+        } else {
+            self.node_depth(self.root_index)
+        }
+    }
+
+    /// Returns the depth of the subtree starting from `node_index` recursively
+    fn node_depth(&self, node_index: usize) -> usize {
+        let node = &self.nodes[node_index];
+        let left = node.left.map_or(0, |index| self.node_depth(index));
+        let right = node.right.map_or(0, |index| self.node_depth(index));
+        1 + left.max(right)
+    }
+}
+
+/// A view of a `KDTree` whose queries measure distances with a [`Metric`], returned by
+/// [`KDTree::using`]. Distances are reported as [`Metric::to_real`] returns them.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricView<'a, P: KdPoint, V, M> {
+    /// Viewed tree
+    tree: &'a KDTree<P, V>,
+    /// Metric used by the queries
+    metric: M,
+}
+
+impl<'a, P: KdPoint, V, M: Metric<P>> MetricView<'a, P, V, M> {
+    /// Finds the nearest neighbor of the specified `point` and returns it with its value.
+    pub fn nearest_neighbor(&self, point: &P) -> Option<(P, &'a V)> {
+        self.nearest_entry(point, |_, _| true).map(Entry::pair)
+    }
+
+    /// Same as [`MetricView::nearest_neighbor`] but returns the handle of the nearest neighbor.
+    pub fn nearest_neighbor_id(&self, point: &P) -> Option<PointId> {
+        self.nearest_entry(point, |_, _| true)
+            .map(|entry| self.tree.point_id(entry))
+    }
+
+    /// Returns the entry of the nearest neighbor of `point` accepted by `filter`
+    fn nearest_entry(
+        &self,
+        point: &P,
+        mut filter: impl FnMut(&P, &V) -> bool,
+    ) -> Option<&'a Entry<P, V>> {
+        let mut best = None;
+        if !self.tree.nodes.is_empty() {
+            self.tree.nearest_neighbor_search(
+                point,
+                self.tree.root_index,
+                &self.metric,
+                &mut filter,
+                &mut best,
+            );
+        }
+        best.map(|(entry, _)| entry)
+    }
+
+    /// Finds the nearest neighbor of the specified `point` among the points accepted by
+    /// `filter`, which is called with the candidate points and their values, and returns it
+    /// with its value. Rejected points are skipped during the search, whose pruning only relies
+    /// on accepted points.
+    pub fn nearest_neighbor_where(
+        &self,
+        point: &P,
+        filter: impl FnMut(&P, &V) -> bool,
+    ) -> Option<(P, &'a V)> {
+        self.nearest_entry(point, filter).map(Entry::pair)
+    }
+
+    /// Finds an approximate nearest neighbor of the specified `point`, whose distance is at most
+    /// $1 + \varepsilon$ times the distance of the nearest neighbor, and returns it with its
+    /// value and the number of nodes examined. A subtree is skipped as soon as its split plane
+    /// is farther than the best candidate divided by $1 + \varepsilon$, so greater values of
+    /// `epsilon` examine fewer nodes. An `epsilon` of `0` gives the exact nearest neighbor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `epsilon` is negative.
+    pub fn approx_nearest_neighbor(&self, point: &P, epsilon: f64) -> Option<(P, &'a V, usize)> {
+        self.approx_nearest_neighbor_bounded(point, epsilon, usize::MAX)
+    }
+
+    /// Same as [`MetricView::approx_nearest_neighbor`] but stops the search once `max_visited`
+    /// nodes have been examined, in which case the neighbor may be farther than
+    /// $1 + \varepsilon$ times the distance of the nearest neighbor. The nodes on the path of
+    /// `point` are examined first. Returns `None` if `max_visited` is `0`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `epsilon` is negative.
+    pub fn approx_nearest_neighbor_bounded(
+        &self,
+        point: &P,
+        epsilon: f64,
+        max_visited: usize,
+    ) -> Option<(P, &'a V, usize)> {
+        assert!(epsilon >= 0., "epsilon must not be negative");
+        if self.tree.nodes.is_empty() {
+            return None;
+        }
+        let mut search = ApproxSearch {
+            best: None,
+            bound: None,
+            factor: (epsilon > 0.).then(|| Real::<P>::from_f64(1. + epsilon)),
+            max_visited,
+            visited: 0,
+        };
+        self.tree.approx_nearest_neighbor_search(
+            point,
+            self.tree.root_index,
+            &self.metric,
+            &mut search,
+        );
+        let (entry, _) = search.best?;
+        let (point, value) = entry.pair();
+        Some((point, value, search.visited))
+    }
+
+    /// Finds the `k` nearest neighbors of the specified `point`, sorted by increasing distance.
+    /// Each point is returned with its value and its distance to `point`. Fewer than `k` points
+    /// are returned when the tree holds less than `k` points.
+    pub fn k_nearest_neighbors(&self, point: &P, k: usize) -> Vec<(P, &'a V, Real<P>)> {
+        self.tree
+            .k_nearest_entries(point, k, &self.metric, |_, _| true)
+            .into_iter()
+            .map(|neighbor| {
+                let (point, value) = neighbor.entry.pair();
+                (point, value, self.metric.to_real(neighbor.distance))
+            })
+            .collect()
+    }
+
+    /// Same as [`MetricView::k_nearest_neighbors`] but only among the points accepted by
+    /// `filter`, see [`MetricView::nearest_neighbor_where`].
+    pub fn k_nearest_neighbors_where(
+        &self,
+        point: &P,
+        k: usize,
+        filter: impl FnMut(&P, &V) -> bool,
+    ) -> Vec<(P, &'a V, Real<P>)> {
+        self.tree
+            .k_nearest_entries(point, k, &self.metric, filter)
+            .into_iter()
+            .map(|neighbor| {
+                let (point, value) = neighbor.entry.pair();
+                (point, value, self.metric.to_real(neighbor.distance))
+            })
+            .collect()
+    }
+
+    /// Finds the farthest point from the specified `point` and returns it with its value.
+    pub fn farthest_point(&self, point: &P) -> Option<(P, &'a V)> {
+        self.tree
+            .k_farthest_entries(point, 1, &self.metric)
+            .first()
+            .map(|neighbor| neighbor.entry.pair())
+    }
+
+    /// Finds the `k` farthest points from the specified `point`, sorted by decreasing distance.
+    /// Each point is returned with its value and its distance to `point`. Fewer than `k` points
+    /// are returned when the tree holds less than `k` points.
+    ///
+    /// Subtrees are pruned with the bounds of their points along each axis, starting from the
+    /// bounding box of the tree.
+    pub fn k_farthest_points(&self, point: &P, k: usize) -> Vec<(P, &'a V, Real<P>)> {
+        self.tree
+            .k_farthest_entries(point, k, &self.metric)
+            .into_iter()
+            .map(|neighbor| {
+                let (point, value) = neighbor.entry.pair();
+                (point, value, self.metric.to_real(neighbor.distance))
+            })
+            .collect()
+    }
+
+    /// Same as [`MetricView::k_nearest_neighbors`] but returns the handles of the neighbors.
+    pub fn k_nearest_neighbor_ids(&self, point: &P, k: usize) -> Vec<(PointId, Real<P>)> {
+        self.tree
+            .k_nearest_entries(point, k, &self.metric, |_, _| true)
+            .into_iter()
+            .map(|neighbor| {
+                (
+                    self.tree.point_id(neighbor.entry),
+                    self.metric.to_real(neighbor.distance),
+                )
+            })
+            .collect()
+    }
+
+    /// Finds all points whose distance to the specified `point` is lower than or equal to
+    /// `radius`. Each point is returned with its value and its distance to `point`, in no
+    /// particular order. A negative or NaN radius holds no points, whatever the metric.
+    pub fn within_radius(&self, point: &P, radius: Real<P>) -> Vec<(P, &'a V, Real<P>)> {
+        self.within_real_radius(point, radius)
+            .into_iter()
+            .map(|(entry, distance)| {
+                let (point, value) = entry.pair();
+                (point, value, self.metric.to_real(distance))
+            })
+            .collect()
+    }
+
+    /// Same as [`MetricView::within_radius`] but the points are sorted by increasing distance.
+    pub fn within_radius_sorted(&self, point: &P, radius: Real<P>) -> Vec<(P, &'a V, Real<P>)> {
+        let mut neighbors = self.within_real_radius(point, radius);
+        neighbors.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        neighbors
+            .into_iter()
+            .map(|(entry, distance)| {
+                let (point, value) = entry.pair();
+                (point, value, self.metric.to_real(distance))
+            })
+            .collect()
+    }
+
+    /// Same as [`MetricView::within_radius`] but returns the handles of the points.
+    pub fn within_radius_ids(&self, point: &P, radius: Real<P>) -> Vec<(PointId, Real<P>)> {
+        self.within_real_radius(point, radius)
+            .into_iter()
+            .map(|(entry, distance)| (self.tree.point_id(entry), self.metric.to_real(distance)))
+            .collect()
+    }
+
+    /// Finds the entries of all points within `radius` of `point` with their distance to `point`
+    fn within_real_radius(
+        &self,
+        point: &P,
+        radius: Real<P>,
+    ) -> Vec<(&'a Entry<P, V>, M::Distance)> {
+        if radius
+            .partial_cmp(&Real::<P>::default())
+            .is_none_or(Ordering::is_lt)
+        {
+            return Vec::new();
+        }
+        self.tree
+            .within_distance(point, self.metric.distance_bound(radius), &self.metric)
+    }
+
+    /// Returns an iterator over the points of the tree by increasing distance to the specified
+    /// `point`. Each point is returned with its value and its distance to `point`.
+    ///
+    /// Nodes are expanded lazily, closest cell first, and a neighbor is returned as soon as no
+    /// queued cell can hold a closer point. Taking the $k$ first neighbors only expands the
+    /// nodes close to them, without knowing $k$ up front, although
+    /// [`MetricView::k_nearest_neighbors`] is faster when $k$ is known.
+    pub fn neighbors_by_distance(self, point: &P) -> NeighborsByDistance<'a, P, V, M> {
+        let mut queue = BinaryHeap::new();
+        if !self.tree.nodes.is_empty() {
+            queue.push(Queued {
+                distance: self.metric.distance(point, point),
+                candidate: Candidate::Node(self.tree.root_index),
+            });
+        }
+        NeighborsByDistance {
+            tree: self.tree,
+            metric: self.metric,
+            point: *point,
+            queue,
+        }
+    }
+}
+
+/// An iterator over the points of a `KDTree` by increasing distance to a target point, returned
+/// by [`MetricView::neighbors_by_distance`]
+#[derive(Debug)]
+pub struct NeighborsByDistance<'a, P: KdPoint, V, M: Metric<P>> {
+    /// Iterated tree
+    tree: &'a KDTree<P, V>,
+    /// Metric measuring the distances
+    metric: M,
+    /// Target point
+    point: P,
+    /// Priority queue of the nodes to expand and of the entries to return, closest first
+    queue: BinaryHeap<Queued<'a, P, V, M::Distance>>,
+}
+
+impl<'a, P: KdPoint, V, M: Metric<P>> NeighborsByDistance<'a, P, V, M> {
+    /// Queues the entries of the nodes on the path from `node_index` towards the target point
+    /// with their distances, and the other children of these nodes with a lower bound of the
+    /// distance to their cell. The cell of a child on the path is as far as the cell of its
+    /// parent, and the cell of the child on the other side of the split plane is at least as far
+    /// as the plane and as the cell of its parent.
+    fn expand(&mut self, node_index: usize, cell_distance: M::Distance) {
+        let mut next = Some(node_index);
+        while let Some(node_index) = next {
+            let node = &self.tree.nodes[node_index];
+            for entry in node.entries() {
+                self.queue.push(Queued {
+                    distance: self.metric.distance(&self.point, &entry.point),
+                    candidate: Candidate::Entry(entry),
+                });
+            }
+
+            let (primary, secondary) = if node.direction(&self.point) {
+                (node.left, node.right)
+            } else {
+                (node.right, node.left)
+            };
+            if let Some(secondary_index) = secondary {
+                let plane_distance = node.plane_distance(&self.point, &self.metric);
+                self.queue.push(Queued {
+                    distance: if plane_distance > cell_distance {
+                        plane_distance
+                    } else {
+                        cell_distance
+                    },
+                    candidate: Candidate::Node(secondary_index),
+                });
+            }
+            next = primary;
+        }
+    }
+}
+
+impl<'a, P: KdPoint, V, M: Metric<P>> Iterator for NeighborsByDistance<'a, P, V, M> {
+    type Item = (P, &'a V, Real<P>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Queued {
+            distance,
+            candidate,
+        }) = self.queue.pop()
+        {
+            match candidate {
+                Candidate::Node(node_index) => self.expand(node_index, distance),
+                Candidate::Entry(entry) => {
+                    let (point, value) = entry.pair();
+                    return Some((point, value, self.metric.to_real(distance)));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<V> KDTree<Point, V> {
+    /// Finds all points inside the specified rectangle, borders included. Each point is returned
+    /// with its value.
+    pub fn range_query(&self, rect: Rectangle) -> Vec<(Point, &V)> {
+        self.range_query_bounds(
+            &rect.position(),
+            &Point::new(rect.x + rect.width, rect.y + rect.height),
+        )
+    }
+
+    /// Store lines into `lines` by traversing the tree using a Depth First Search approach. Each
+    /// node draws its split plane clipped to its cell `bounds`, whose corners are
+    /// `(bounds.x, bounds.y)` and `(bounds.width, bounds.height)`.
+    fn dfs_lines(&self, node_index: usize, lines: &mut Vec<geometry::Line>, bounds: Rectangle) {
+        let node = &self.nodes[node_index];
+        let point = node.point;
+        let (line, left_bounds, right_bounds) = match node.split {
+            0 => (
+                geometry::Line::PointToPoint(
+                    Point::new(point.x, bounds.y),
+                    Point::new(point.x, bounds.height),
+                ),
+                Rectangle {
+                    width: point.x,
+                    ..bounds
+                },
+                Rectangle {
+                    x: point.x,
+                    ..bounds
+                },
+            ),
+            _ => (
+                geometry::Line::PointToPoint(
+                    Point::new(bounds.x, point.y),
+                    Point::new(bounds.width, point.y),
+                ),
+                Rectangle {
+                    height: point.y,
+                    ..bounds
+                },
+                Rectangle {
+                    y: point.y,
+                    ..bounds
+                },
+            ),
+        };
+        lines.push(line);
+        if let Some(index) = node.left {
+            self.dfs_lines(index, lines, left_bounds);
+        }
+        if let Some(index) = node.right {
+            self.dfs_lines(index, lines, right_bounds);
+        }
+    }
+
+    /// Returns the lines drew by `Geometry`
+    pub fn lines(&self) -> Vec<geometry::Line> {
+        if !self.nodes.is_empty() {
+            let mut lines = Vec::new();
+            self.dfs_lines(
+                self.root_index,
+                &mut lines,
+                Rectangle {
+                    x: 0.,
+                    y: 0.,
+                    width: 1.,
+                    height: 1.,
+                },
+            );
+            lines
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Returns [`KDTreeError::NonFiniteCoordinate`] if a coordinate of `point` is NaN or infinite
+fn check_finite<P: KdPoint>(point: &P) -> Result<(), KDTreeError> {
+    if (0..P::dim()).all(|axis| point.coord(axis).is_finite()) {
+        Ok(())
+    } else {
+        Err(KDTreeError::NonFiniteCoordinate)
+    }
+}
+
+/// Behavior of [`KDTree::insert`] when the tree already holds a point equal to the inserted
+/// one, see [`KDTree::with_duplicate_policy`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Equal points are inserted as distinct points with their own values and handles, so that
+    /// the tree is a multiset of points, see [`KDTree::count`]
+    #[default]
+    Allow,
+    /// The point is not inserted and the tree keeps the value of the equal point
+    Reject,
+    /// The value of the equal point is replaced, and its handle is kept
+    Replace,
+}
+
+/// Strategy choosing the split axis of the nodes when a subtree is built, see
+/// [`KDTree::with_split_strategy`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Cycles through the axes: the children of a node split along the axis $i$ are split along
+    /// the axis $(i + 1) \bmod K$. Each node is the median of its points.
+    #[default]
+    Alternating,
+    /// Splits along the axis where the points have the widest spread, at their median
+    WidestSpread,
+    /// Splits along the axis where the coordinates of the points have the highest variance, at
+    /// their median
+    HighestVariance,
+    /// Splits along the axis where the points have the widest spread, at the point closest to the
+    /// middle of the spread. The midpoint plane slides to the nearest point so that no cell is
+    /// empty, which gives cells with a bounded aspect ratio rather than a balanced tree.
+    SlidingMidpoint,
+}
+
+impl SplitStrategy {
+    /// Partially sorts `entries` around the point of their node and returns the split axis with
+    /// the index of this point. `split` is the axis given by the alternation from the parent
+    /// node. See [`median_split`] for the order of the entries.
+    fn split<P: KdPoint, V>(self, entries: &mut [Entry<P, V>], split: usize) -> (usize, usize) {
+        let axis = match self {
+            SplitStrategy::Alternating => split,
+            SplitStrategy::WidestSpread | SplitStrategy::SlidingMidpoint => widest_axis(entries),
+            SplitStrategy::HighestVariance => highest_variance_axis(entries),
+        };
+        let index = match self {
+            SplitStrategy::SlidingMidpoint => midpoint_split(entries, axis),
+            _ => median_split(entries, axis),
+        };
+        (axis, index)
+    }
+}
+
+/// Returns the axis where the coordinates of the points of `entries` have the widest range. The
+/// first axis is returned if `entries` is empty.
+fn widest_axis<P: KdPoint, V>(entries: &[Entry<P, V>]) -> usize {
+    let mut best = (0, None);
+    for axis in 0..P::dim() {
+        if let Some((min, max)) = coord_range(entries, axis) {
+            let spread = max.squared_difference(min);
+            if best.1.is_none_or(|widest| spread > widest) {
+                best = (axis, Some(spread));
+            }
+        }
+    }
+    best.0
+}
+
+/// Returns the axis where the coordinates of the points of `entries` have the highest variance
+fn highest_variance_axis<P: KdPoint, V>(entries: &[Entry<P, V>]) -> usize {
+    let count = entries.len() as f64;
+    let mut best = (0, f64::NEG_INFINITY);
+    for axis in 0..P::dim() {
+        let mean = entries
+            .iter()
+            .map(|entry| entry.point.coord(axis).to_f64())
+            .sum::<f64>()
+            / count;
+        let variance = entries
+            .iter()
+            .map(|entry| (entry.point.coord(axis).to_f64() - mean).powi(2))
+            .sum::<f64>();
+        if variance > best.1 {
+            best = (axis, variance);
+        }
+    }
+    best.0
+}
+
+/// Returns the minimum and the maximum coordinates along `axis` of the points of `entries`
+fn coord_range<P: KdPoint, V>(
+    entries: &[Entry<P, V>],
+    axis: usize,
+) -> Option<(P::Scalar, P::Scalar)> {
+    let mut coords = entries.iter().map(|entry| entry.point.coord(axis));
+    let first = coords.next()?;
+    Some(coords.fold((first, first), |(min, max), value| {
+        (
+            if value < min { value } else { min },
+            if value > max { value } else { max },
+        )
+    }))
+}
+
+/// Returns an upper bound of the distance between `point` and the points of an axis-aligned
+/// `cell` given by its bounds along each axis, which is the distance to its farthest corner.
+fn cell_max_distance<P: KdPoint, M: Metric<P>>(
+    point: &P,
+    cell: &[(P::Scalar, P::Scalar)],
+    metric: &M,
+) -> M::Distance {
+    cell.iter().enumerate().fold(
+        metric.distance(point, point),
+        |distance, (axis, (min, max))| {
+            let to_min = metric.plane_distance(point, axis, *min);
+            let to_max = metric.plane_distance(point, axis, *max);
+            metric.combine(distance, if to_min > to_max { to_min } else { to_max })
+        },
+    )
+}
+
+/// Returns the maximum depth $\lfloor \log_{1/\alpha}(n) \rfloor$ allowed for a node in a tree of
+/// size $n$ before rebalancing.
+fn max_depth(size: usize) -> usize {
+    ((size as f32).ln() / (1. / ALPHA).ln()).floor() as usize
+}
+
+/// Returns the split axis of the children of a node split along `split`, cycling through the
+/// $K$ dimensions.
+fn next_split<P: KdPoint>(split: usize) -> usize {
+    (split + 1) % P::dim()
+}
+
+/// Partially sorts `entries` along the `split` axis of their points and returns the index of the
+/// median. All points before the median have a lower or equal coordinate and all points after
+/// have a strictly greater coordinate, which matches `Node::direction`.
+fn median_split<P: KdPoint, V>(entries: &mut [Entry<P, V>], split: usize) -> usize {
+    rank_split(entries, split, entries.len() / 2)
+}
+
+/// Same as [`median_split`] but splits at the point whose coordinate is the closest to the middle
+/// of the range of the coordinates along the `split` axis.
+fn midpoint_split<P: KdPoint, V>(entries: &mut [Entry<P, V>], split: usize) -> usize {
+    let Some((min, max)) = coord_range(entries, split) else {
+        return 0;
+    };
+    let middle = (min.to_f64() + max.to_f64()) / 2.;
+    let gap = |entry: &Entry<P, V>| (entry.point.coord(split).to_f64() - middle).abs();
+    let closest = entries
+        .iter()
+        .min_by(|a, b| gap(a).total_cmp(&gap(b)))
+        .map(|entry| entry.point.coord(split))
+        .unwrap_or(min);
+    let rank = entries
+        .iter()
+        .filter(|entry| entry.point.coord(split) < closest)
+        .count();
+    rank_split(entries, split, rank)
+}
+
+/// Partially sorts `entries` along the `split` axis of their points around the point of the
+/// specified `rank` and returns its index, after moving the points with the same coordinate
+/// before it. See [`median_split`].
+fn rank_split<P: KdPoint, V>(entries: &mut [Entry<P, V>], split: usize, rank: usize) -> usize {
+    let mut pivot = rank;
+    entries.select_nth_unstable_by(pivot, |a, b| {
+        a.point.coord(split).total_cmp(&b.point.coord(split))
+    });
+    let value = entries[pivot].point.coord(split);
+    for index in pivot + 1..entries.len() {
+        if entries[index].point.coord(split) == value {
+            pivot += 1;
+            entries.swap(index, pivot);
+        }
+    }
+    pivot
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::metric::{Chebyshev, Manhattan, Minkowski};
+    use crate::point::{distance, squared_distance};
     use rand::random;
+    use std::cell::Cell;
+
+    thread_local! {
+        /// Number of subtrees rebuilt by the current test
+        pub static REBUILDS: Cell<usize> = const { Cell::new(0) };
+    }
 
     fn random_point() -> Point {
         Point::new(random::<f32>(), random::<f32>())
@@ -358,20 +2757,585 @@ mod tests {
         for _ in 0..100 {
             let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
             let target = random_point();
-            let tree = KDTree::from_points(&points);
-            let actual_neighbor = tree.nearest_neighbor(&target).unwrap();
+            let tree = KDTree::from_points(&points);
+            let (actual_neighbor, _) = tree.nearest_neighbor(&target).unwrap();
+            let expected_neighbor = points
+                .iter()
+                .min_by(|a, b| {
+                    distance(*a, &target)
+                        .partial_cmp(&distance(*b, &target))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .unwrap();
+            assert_eq!(actual_neighbor, *expected_neighbor);
+        }
+    }
+
+    #[test]
+    fn test_k_nearest_points() {
+        for _ in 0..100 {
+            let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+            let target = random_point();
+            let k = rand::random_range(1..20);
+            let tree = KDTree::from_points(&points);
+            let actual_neighbors = tree.k_nearest_neighbors(&target, k);
+            let mut expected_neighbors = points.clone();
+            expected_neighbors
+                .sort_by(|a, b| distance(a, &target).total_cmp(&distance(b, &target)));
+            assert_eq!(actual_neighbors.len(), k);
+            for ((actual, _, actual_distance), expected) in
+                actual_neighbors.iter().zip(expected_neighbors)
+            {
+                assert_eq!(*actual, expected);
+                assert_eq!(*actual_distance, distance(&expected, &target));
+            }
+        }
+    }
+
+    #[test]
+    fn test_k_nearest_points_more_than_size() {
+        let points: Vec<Point> = (0..10).map(|_| random_point()).collect();
+        let tree = KDTree::from_points(&points);
+        assert_eq!(tree.k_nearest_neighbors(&random_point(), 20).len(), 10);
+        assert!(tree.k_nearest_neighbors(&random_point(), 0).is_empty());
+        assert!(
+            KDTree::<Point>::default()
+                .k_nearest_neighbors(&random_point(), 5)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_within_radius() {
+        for _ in 0..100 {
+            let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+            let target = random_point();
+            let radius = rand::random_range(0.0..0.2);
+            let tree = KDTree::from_points(&points);
+            let actual_neighbors = tree.within_radius_sorted(&target, radius);
+            let mut expected_neighbors: Vec<Point> = points
+                .iter()
+                .copied()
+                .filter(|point| distance(point, &target) <= radius)
+                .collect();
+            expected_neighbors
+                .sort_by(|a, b| distance(a, &target).total_cmp(&distance(b, &target)));
+            let actual_points: Vec<Point> = actual_neighbors
+                .iter()
+                .map(|(point, _, _)| *point)
+                .collect();
+            assert_eq!(actual_points, expected_neighbors);
+            assert!(
+                actual_neighbors
+                    .windows(2)
+                    .all(|window| window[0].1 <= window[1].1)
+            );
+            assert_eq!(
+                tree.within_radius(&target, radius).len(),
+                actual_neighbors.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_within_negative_radius() {
+        let points: Vec<Point> = (0..100).map(|_| random_point()).collect();
+        let tree = KDTree::from_points(&points);
+        let target = points[0];
+        for radius in [-0.2, f32::NAN] {
+            assert!(tree.within_radius(&target, radius).is_empty());
+            assert!(tree.within_radius_ids(&target, radius).is_empty());
+            assert!(
+                tree.using(Manhattan)
+                    .within_radius(&target, radius)
+                    .is_empty()
+            );
+            assert!(
+                tree.using(Chebyshev)
+                    .within_radius(&target, radius)
+                    .is_empty()
+            );
+        }
+        assert_eq!(tree.within_radius(&target, 0.).len(), 1);
+    }
+
+    #[test]
+    fn test_within_radius_on_boundary() {
+        let points = [
+            Point::new(0.5, 0.5),
+            Point::new(0.2, 0.6),
+            Point::new(0.8, 0.4),
+            Point::new(0.5, 0.9),
+        ];
+        let tree = KDTree::from_points(&points);
+        let neighbors = tree.within_radius_sorted(&Point::new(0.5, 0.6), 0.3);
+        let points: Vec<Point> = neighbors.iter().map(|(point, _, _)| *point).collect();
+        assert!(points.contains(&Point::new(0.2, 0.6)));
+        assert!(points.contains(&Point::new(0.5, 0.5)));
+        assert!(points.contains(&Point::new(0.5, 0.9)));
+        assert!(!points.contains(&Point::new(0.8, 0.4)));
+    }
+
+    #[test]
+    fn test_range_query() {
+        for _ in 0..100 {
+            let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+            let corner = random_point();
+            let rect = Rectangle {
+                x: corner.x,
+                y: corner.y,
+                width: rand::random_range(0.0..0.5),
+                height: rand::random_range(0.0..0.5),
+            };
+            let tree = KDTree::from_points(&points);
+            let mut actual_points: Vec<Point> = tree
+                .range_query(rect)
+                .into_iter()
+                .map(|(point, _)| point)
+                .collect();
+            let mut expected_points: Vec<Point> = points
+                .iter()
+                .copied()
+                .filter(|point| {
+                    rect.x <= point.x
+                        && point.x <= rect.x + rect.width
+                        && rect.y <= point.y
+                        && point.y <= rect.y + rect.height
+                })
+                .collect();
+            let by_coordinates =
+                |a: &Point, b: &Point| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y));
+            actual_points.sort_by(by_coordinates);
+            expected_points.sort_by(by_coordinates);
+            assert_eq!(actual_points, expected_points);
+        }
+    }
+
+    #[test]
+    fn test_range_query_on_split_planes() {
+        let points = [
+            Point::new(0.5, 0.5),
+            Point::new(0.5, 0.2),
+            Point::new(0.7, 0.5),
+            Point::new(0.3, 0.5),
+        ];
+        let tree = KDTree::from_points(&points);
+        let rect = Rectangle {
+            x: 0.5,
+            y: 0.5,
+            width: 0.2,
+            height: 0.,
+        };
+        let actual_points: Vec<Point> = tree
+            .range_query(rect)
+            .into_iter()
+            .map(|(point, _)| point)
+            .collect();
+        assert_eq!(actual_points.len(), 2);
+        assert!(actual_points.contains(&Point::new(0.5, 0.5)));
+        assert!(actual_points.contains(&Point::new(0.7, 0.5)));
+    }
+
+    #[test]
+    fn test_balanced_construction() {
+        // Sorted points without duplicated coordinates
+        let points: Vec<Point> = (0..1_000)
+            .map(|i| Point::new(i as f32 / 1_000., ((i * 7_919) % 1_000) as f32 / 1_000.))
+            .collect();
+        let tree = KDTree::from_points(&points);
+        assert_eq!(tree.len(), 1_000);
+        assert_eq!(tree.depth(), 10);
+    }
+
+    /// Checks that the size of each node matches the number of points in its subtree, that only
+    /// leaves hold a bucket and that only live nodes hold points equal to their own
+    fn check_sizes<P: KdPoint, V>(tree: &KDTree<P, V>, node_index: Option<usize>) -> usize {
+        match node_index {
+            Some(index) => {
+                let node = &tree.nodes[index];
+                assert!(node.bucket.is_empty() || node.is_leaf() && !node.is_dead());
+                assert!(node.bucket.len() < tree.bucket_size);
+                assert!(node.duplicates.is_empty() || !node.is_dead());
+                assert!(
+                    node.duplicates
+                        .iter()
+                        .all(|entry| entry.point == node.point)
+                );
+                let size = node.entries().count()
+                    + check_sizes(tree, node.left)
+                    + check_sizes(tree, node.right);
+                assert_eq!(node.size, size);
+                size
+            }
+            None => 0,
+        }
+    }
+
+    #[test]
+    fn test_sequential_insertion_balance() {
+        let mut points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        points.sort_by(|a, b| a.x.total_cmp(&b.x));
+        let mut tree = KDTree::default();
+        for point in points.iter() {
+            tree.add_point(*point);
+            assert!(tree.depth() <= max_depth(tree.len()) + 1);
+        }
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 1_000);
+    }
+
+    #[test]
+    fn test_random_operations_balance() {
+        let mut points: Vec<Point> = Vec::new();
+        let mut tree = KDTree::default();
+        for _ in 0..5_000 {
+            if !points.is_empty() && rand::random_bool(0.4) {
+                let point = points.swap_remove(rand::random_range(0..points.len()));
+                tree.remove_point(point);
+            } else {
+                let point = Point::new(random::<f32>(), random::<f32>() * 0.01);
+                points.push(point);
+                tree.add_point(point);
+            }
+            assert_eq!(tree.len(), points.len());
+            assert!(tree.depth() <= max_depth(tree.len()) + 2);
+        }
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+        let target = random_point();
+        let expected_neighbor = points
+            .iter()
+            .min_by(|a, b| distance(*a, &target).total_cmp(&distance(*b, &target)))
+            .copied();
+        assert_eq!(
+            tree.nearest_neighbor(&target).map(|(point, _)| point),
+            expected_neighbor
+        );
+    }
+
+    #[test]
+    fn test_equal_points_insertion_balance() {
+        let mut tree = KDTree::default();
+        for _ in 0..2_000 {
+            tree.add_point(Point::new(0.5, 0.5));
+        }
+        assert_eq!(tree.depth(), 1);
+        assert_eq!(REBUILDS.get(), 0);
+
+        for _ in 0..2_000 {
+            tree.add_point(Point::new(
+                rand::random_range(0..10) as f32,
+                rand::random_range(0..10) as f32,
+            ));
+        }
+        assert!(REBUILDS.get() < 100);
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 4_000);
+    }
+
+    #[test]
+    fn test_balanced_construction_with_duplicates() {
+        let points: Vec<Point> = (0..1_000)
+            .map(|i| Point::new((i % 7) as f32 / 7., (i % 3) as f32 / 3.))
+            .collect();
+        let tree = KDTree::from_points(&points);
+        let mut actual_points = tree.points();
+        let mut expected_points = points.clone();
+        let by_coordinates = |a: &Point, b: &Point| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y));
+        actual_points.sort_by(by_coordinates);
+        expected_points.sort_by(by_coordinates);
+        assert_eq!(actual_points, expected_points);
+        for point in points.iter() {
+            assert_eq!(tree.nearest_neighbor(point), Some((*point, &())));
+        }
+
+        let mut tree = tree;
+        tree.remove_point(points[0]);
+        assert_eq!(tree.len(), 999);
+    }
+
+    #[test]
+    fn test_construction_with_many_equal_points() {
+        let points = vec![Point::new(0.5, 0.5); 200_000];
+        let tree = KDTree::from_points(&points);
+        assert_eq!(tree.len(), 200_000);
+        let root = &tree.nodes[tree.root_index];
+        assert_eq!(root.point, points[0]);
+        assert_eq!(root.duplicates.len(), 199_999);
+        assert!(root.is_leaf());
+    }
+
+    #[test]
+    fn test_three_dimensional_points() {
+        for _ in 0..100 {
+            let points: Vec<[f32; 3]> = (0..1_000).map(|_| random::<[f32; 3]>()).collect();
+            let target = random::<[f32; 3]>();
+            let mut tree = KDTree::from_points(&points);
+            let mut expected_neighbors = points.clone();
+            expected_neighbors
+                .sort_by(|a, b| distance(a, &target).total_cmp(&distance(b, &target)));
+            assert_eq!(
+                tree.nearest_neighbor(&target),
+                Some((expected_neighbors[0], &()))
+            );
+            let actual_neighbors: Vec<[f32; 3]> = tree
+                .k_nearest_neighbors(&target, 10)
+                .into_iter()
+                .map(|(point, _, _)| point)
+                .collect();
+            assert_eq!(actual_neighbors, expected_neighbors[..10]);
+
+            tree.remove_point(expected_neighbors[0]);
+            assert_eq!(tree.len(), 999);
+            assert_eq!(
+                tree.nearest_neighbor(&target),
+                Some((expected_neighbors[1], &()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_exact_integer_coordinates() {
+        // Both points are at the same `f64` distance of the origin but `closer` is nearer.
+        let farther = [1_000_000_000_000_000_i64, 0];
+        let closer = [999_999_999_999_999_i64, 44_721_359];
+        assert_eq!(distance(&farther, &[0, 0]), distance(&closer, &[0, 0]));
+        for points in [[farther, closer], [closer, farther]] {
+            let tree = KDTree::from_points(&points);
+            assert_eq!(tree.nearest_neighbor(&[0, 0]), Some((closer, &())));
+            let neighbors = tree.k_nearest_neighbors(&[0, 0], 2);
+            assert_eq!(neighbors[0].0, closer);
+            assert_eq!(neighbors[1].0, farther);
+        }
+    }
+
+    #[test]
+    fn test_extreme_integer_coordinates() {
+        assert_eq!(
+            squared_distance(&[i64::MIN, i64::MIN], &[i64::MAX, i64::MAX]),
+            u128::MAX
+        );
+        // The squared distance of `farther` would wrap around below the one of `closer`.
+        let target = [i64::MAX, i64::MIN];
+        let farther = [i64::MIN, 0];
+        let closer = [0, 0];
+        assert_eq!(squared_distance(&farther, &target), u128::MAX);
+        let tree = KDTree::from_points(&[farther, closer]);
+        assert_eq!(tree.nearest_neighbor(&target), Some((closer, &())));
+        let neighbors = tree.k_nearest_neighbors(&target, 2);
+        assert_eq!(neighbors[0].0, closer);
+        assert_eq!(neighbors[1].0, farther);
+        let neighbors = tree.within_radius(&target, 1.5e19);
+        assert_eq!(neighbors.len(), 1);
+        assert_eq!(neighbors[0].0, closer);
+    }
+
+    #[test]
+    fn test_integer_coordinates() {
+        for _ in 0..100 {
+            let points: Vec<[i32; 2]> = (0..1_000)
+                .map(|_| [random::<i32>() / 4, random::<i32>() / 4])
+                .collect();
+            let target = [random::<i32>() / 4, random::<i32>() / 4];
+            let tree = KDTree::from_points(&points);
+            let expected_distance = points
+                .iter()
+                .map(|point| squared_distance(point, &target))
+                .min();
+            let actual_distance = tree
+                .nearest_neighbor(&target)
+                .map(|(point, _)| squared_distance(&point, &target));
+            assert_eq!(actual_distance, expected_distance);
+
+            let radius = 500_000_000.;
+            let mut actual_neighbors: Vec<[i32; 2]> = tree
+                .within_radius(&target, radius)
+                .into_iter()
+                .map(|(point, _, _)| point)
+                .collect();
+            let mut expected_neighbors: Vec<[i32; 2]> = points
+                .iter()
+                .copied()
+                .filter(|point| distance(point, &target) <= radius)
+                .collect();
+            actual_neighbors.sort();
+            expected_neighbors.sort();
+            assert_eq!(actual_neighbors, expected_neighbors);
+        }
+    }
+
+    #[test]
+    fn test_double_precision_coordinates() {
+        let offset = 1e9;
+        let points: Vec<[f64; 2]> = (0..1_000)
+            .map(|_| [offset + random::<f64>(), offset + random::<f64>()])
+            .collect();
+        let target = [offset + random::<f64>(), offset + random::<f64>()];
+        let tree = KDTree::from_points(&points);
+        let expected_neighbor = points
+            .iter()
+            .min_by(|a, b| squared_distance(*a, &target).total_cmp(&squared_distance(*b, &target)))
+            .copied();
+        assert_eq!(
+            tree.nearest_neighbor(&target).map(|(point, _)| point),
+            expected_neighbor
+        );
+    }
+
+    #[test]
+    fn test_values() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::from_entries(
+            points
+                .iter()
+                .enumerate()
+                .map(|(index, point)| (*point, index))
+                .collect(),
+        );
+        for (index, point) in points.iter().enumerate() {
+            assert_eq!(tree.get_by_point(point), Some(&index));
+            assert_eq!(tree.nearest_neighbor(point), Some((*point, &index)));
+        }
+        for (point, value, _) in tree.k_nearest_neighbors(&random_point(), 10) {
+            assert_eq!(points[*value], point);
+        }
+
+        let target = rand::random_range(0..1_000);
+        if let Some(value) = tree.get_by_point_mut(&points[target]) {
+            *value += 1_000;
+        }
+        assert_eq!(tree.get_by_point(&points[target]), Some(&(target + 1_000)));
+        assert_eq!(tree.remove_point(points[target]), Some(target + 1_000));
+        assert_eq!(tree.get_by_point(&points[target]), None);
+        assert_eq!(tree.remove_point(points[target]), None);
+
+        let point = random_point();
+        tree.insert(point, 2_000);
+        assert_eq!(tree.get_by_point(&point), Some(&2_000));
+        assert_eq!(tree.len(), 1_000);
+        assert_eq!(tree.iter().map(|(_, value)| *value).max(), Some(2_000));
+    }
+
+    #[test]
+    fn test_handles() {
+        let mut tree = KDTree::default();
+        let mut points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        // Sorted insertions trigger rebuilds which move points between nodes
+        points.sort_by(|a, b| a.x.total_cmp(&b.x));
+        let ids: Vec<PointId> = points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| tree.insert(*point, index).id())
+            .collect();
+        for (index, id) in ids.iter().enumerate() {
+            assert_eq!(tree.get(*id), Some((points[index], &index)));
+        }
+
+        let target = rand::random_range(0..1_000);
+        let (_, value) = tree.nearest_neighbor(&points[target]).unwrap();
+        assert_eq!(*value, target);
+        assert_eq!(tree.nearest_neighbor_id(&points[target]), Some(ids[target]));
+        if let Some(value) = tree.get_mut(ids[target]) {
+            *value = 0;
+        }
+        assert_eq!(tree.remove(ids[target]), Some((points[target], 0)));
+        assert_eq!(tree.get(ids[target]), None);
+        assert_eq!(tree.remove(ids[target]), None);
+
+        // The slot of the removed point is reused without aliasing the old handle
+        let id = tree.insert(random_point(), 1_000).id();
+        assert_eq!(id.index, ids[target].index);
+        assert_ne!(id, ids[target]);
+        assert_eq!(tree.get(ids[target]), None);
+        for (index, id) in ids.iter().enumerate().filter(|(index, _)| *index != target) {
+            assert_eq!(tree.get(*id), Some((points[index], &index)));
+        }
+
+        let neighbors = tree.k_nearest_neighbor_ids(&points[0], 5);
+        assert_eq!(neighbors.len(), 5);
+        for (id, distance) in neighbors {
+            let (point, _) = tree.get(id).unwrap();
+            assert_eq!(crate::point::distance(&point, &points[0]), distance);
+        }
+    }
+
+    #[test]
+    fn test_handles_coincident_points() {
+        let point = random_point();
+        let mut tree = KDTree::default();
+        let first = tree.insert(point, "first").id();
+        let second = tree.insert(point, "second").id();
+        let third = tree.insert(point, "third").id();
+        assert_eq!(tree.remove(second), Some((point, "second")));
+        assert_eq!(tree.get(first), Some((point, &"first")));
+        assert_eq!(tree.get(third), Some((point, &"third")));
+        assert_eq!(tree.remove(first), Some((point, "first")));
+        assert_eq!(tree.within_radius_ids(&point, 0.), vec![(third, 0.)]);
+    }
+
+    /// Checks that the points of the left subtree of each node are lower than or equal to the node
+    /// point and the points of the right subtree are greater along the split axis
+    fn check_order<P: KdPoint, V>(tree: &KDTree<P, V>) {
+        for node in tree.nodes.values() {
+            let value = node.point.coord(node.split);
+            if let Some(index) = node.left {
+                assert!(tree.subtree_max(index, node.split) <= value);
+            }
+            if let Some(index) = node.right {
+                assert!(tree.subtree_min(index, node.split) > value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_move_points() {
+        let mut points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::default();
+        let ids: Vec<PointId> = points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| tree.insert(*point, index).id())
+            .collect();
+        for _ in 0..10 {
+            for (index, id) in ids.iter().enumerate() {
+                let step = if rand::random_bool(0.1) { 0.5 } else { 0.01 };
+                let point = Point::new(
+                    points[index].x + rand::random_range(-step..step),
+                    points[index].y + rand::random_range(-step..step),
+                );
+                assert!(tree.move_to(*id, point));
+                points[index] = point;
+            }
+            check_order(&tree);
+            assert_eq!(check_sizes(&tree, Some(tree.root_index)), 1_000);
+            for (index, id) in ids.iter().enumerate() {
+                assert_eq!(tree.get(*id), Some((points[index], &index)));
+            }
+            let target = random_point();
             let expected_neighbor = points
                 .iter()
-                .min_by(|a, b| {
-                    a.distance(target)
-                        .partial_cmp(&b.distance(target))
-                        .unwrap_or(std::cmp::Ordering::Equal)
-                })
-                .unwrap();
-            assert_eq!(actual_neighbor, *expected_neighbor);
+                .min_by(|a, b| distance(*a, &target).total_cmp(&distance(*b, &target)))
+                .copied();
+            assert_eq!(
+                tree.nearest_neighbor(&target).map(|(point, _)| point),
+                expected_neighbor
+            );
         }
     }
 
+    #[test]
+    fn test_update_point() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::from_points(&points);
+        let id = tree.nearest_neighbor_id(&points[0]).unwrap();
+        let point = random_point();
+        assert!(tree.update_point(points[0], point));
+        assert!(!tree.update_point(points[0], point));
+        assert_eq!(tree.get(id), Some((point, &())));
+        assert_eq!(tree.len(), 1_000);
+        check_order(&tree);
+
+        tree.remove(id);
+        assert!(!tree.move_to(id, points[0]));
+    }
+
     #[test]
     fn test_deletion_with_match() {
         for _ in 0..100 {
@@ -381,7 +3345,7 @@ mod tests {
             let point = points[target];
             tree.remove_point(point);
             let points = tree.points();
-            assert_eq!(tree.nodes.len(), 999);
+            assert_eq!(tree.len(), 999);
             assert!(!points.contains(&point));
         }
     }
@@ -395,7 +3359,1101 @@ mod tests {
             tree.remove_point(point);
             let points = tree.points();
             assert!(!points.contains(&point));
-            assert_eq!(tree.nodes.len(), 1000);
+            assert_eq!(tree.len(), 1000);
+        }
+    }
+
+    #[test]
+    fn test_compaction() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::default();
+        let ids: Vec<PointId> = points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| tree.insert(*point, index).id())
+            .collect();
+        for id in ids.iter().take(900) {
+            tree.remove(*id);
+            assert!(tree.nodes.free_len() <= tree.len());
+        }
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 100);
+        for (index, id) in ids.iter().enumerate().skip(900) {
+            assert_eq!(tree.get(*id), Some((points[index], &index)));
+        }
+
+        tree.compact();
+        assert_eq!(tree.nodes.free_len(), 0);
+        assert_eq!(tree.root_index, 0);
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 100);
+        for (index, id) in ids.iter().enumerate().skip(900) {
+            assert_eq!(tree.get(*id), Some((points[index], &index)));
+            let (point, _) = tree.nearest_neighbor(&points[index]).unwrap();
+            assert_eq!(point, points[index]);
+        }
+    }
+
+    #[test]
+    fn test_bucketed_leaves() {
+        let mut points: Vec<Point> = Vec::new();
+        let mut ids: Vec<PointId> = Vec::new();
+        let mut tree = KDTree::default().with_bucket_size(16);
+        for _ in 0..5_000 {
+            if !points.is_empty() && rand::random_bool(0.3) {
+                let index = rand::random_range(0..points.len());
+                assert_eq!(
+                    tree.remove(ids.swap_remove(index)),
+                    Some((points[index], ()))
+                );
+                points.swap_remove(index);
+            } else {
+                let point = random_point();
+                points.push(point);
+                ids.push(tree.add_point(point).id());
+            }
+        }
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+        assert!(tree.nodes.len() < tree.len() / 2);
+        check_order(&tree);
+
+        for (index, id) in ids.iter().enumerate() {
+            assert_eq!(tree.get(*id), Some((points[index], &())));
+            let point = Point::new(points[index].x + 0.01, points[index].y - 0.01);
+            assert!(tree.move_to(*id, point));
+            points[index] = point;
+        }
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+        check_order(&tree);
+
+        let target = random_point();
+        let (nearest, _) = tree.nearest_neighbor(&target).unwrap();
+        let expected = points
+            .iter()
+            .map(|point| distance(point, &target))
+            .min_by(f32::total_cmp)
+            .unwrap();
+        assert_eq!(distance(&nearest, &target), expected);
+        let neighbors = tree.k_nearest_neighbors(&target, 20);
+        let mut distances: Vec<f32> = points
+            .iter()
+            .map(|point| distance(point, &target))
+            .collect();
+        distances.sort_by(f32::total_cmp);
+        assert_eq!(
+            neighbors
+                .iter()
+                .map(|(_, _, distance)| *distance)
+                .collect::<Vec<f32>>(),
+            distances[..20]
+        );
+        let mut found = tree.range_query_bounds(&Point::new(0.2, 0.2), &Point::new(0.6, 0.6));
+        let mut expected: Vec<Point> = points
+            .iter()
+            .filter(|point| (0.2..=0.6).contains(&point.x) && (0.2..=0.6).contains(&point.y))
+            .copied()
+            .collect();
+        let by_coordinates = |a: &Point, b: &Point| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y));
+        found.sort_by(|(a, _), (b, _)| by_coordinates(a, b));
+        expected.sort_by(by_coordinates);
+        assert_eq!(
+            found
+                .into_iter()
+                .map(|(point, _)| point)
+                .collect::<Vec<Point>>(),
+            expected
+        );
+
+        // Points are removed from the leaves until the tree is empty
+        for (index, point) in points.iter().enumerate() {
+            assert_eq!(tree.remove_point(*point), Some(()));
+            assert_eq!(tree.len(), points.len() - index - 1);
+        }
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_bucket_size_rebuild() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let tree = KDTree::from_points(&points);
+        assert_eq!(tree.nodes.len(), 1_000);
+        let tree = tree.with_bucket_size(8);
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 1_000);
+        // 1000 points are split into 128 leaves of 7 or 8 points
+        assert_eq!(tree.depth(), 8);
+        assert_eq!(tree.nodes.len(), 255);
+    }
+
+    #[test]
+    fn test_split_strategies() {
+        let strategies = [
+            SplitStrategy::Alternating,
+            SplitStrategy::WidestSpread,
+            SplitStrategy::HighestVariance,
+            SplitStrategy::SlidingMidpoint,
+        ];
+        for strategy in strategies {
+            // Points along a vertical road
+            let mut points: Vec<Point> = (0..1_000)
+                .map(|_| Point::new(0.5 + random::<f32>() * 0.01, random::<f32>()))
+                .collect();
+            let mut tree = KDTree::from_points(&points).with_split_strategy(strategy);
+            let root_split = match strategy {
+                SplitStrategy::Alternating => 0,
+                _ => 1,
+            };
+            assert_eq!(tree.nodes[tree.root_index].split, root_split);
+            for _ in 0..1_000 {
+                if rand::random_bool(0.5) {
+                    let point = points.swap_remove(rand::random_range(0..points.len()));
+                    tree.remove_point(point);
+                } else {
+                    let point = Point::new(0.5 + random::<f32>() * 0.01, random::<f32>());
+                    points.push(point);
+                    tree.add_point(point);
+                }
+            }
+            check_order(&tree);
+            assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+
+            let target = random_point();
+            let expected_neighbor = points
+                .iter()
+                .min_by(|a, b| distance(*a, &target).total_cmp(&distance(*b, &target)))
+                .copied();
+            assert_eq!(
+                tree.nearest_neighbor(&target).map(|(point, _)| point),
+                expected_neighbor
+            );
+            let mut found = tree.range_query(Rectangle::new(
+                Point::new(0.5, 0.25),
+                iced::Size::new(0.005, 0.5),
+            ));
+            let mut expected: Vec<Point> = points
+                .iter()
+                .filter(|point| {
+                    (0.5..=0.505).contains(&point.x) && (0.25..=0.75).contains(&point.y)
+                })
+                .copied()
+                .collect();
+            found.sort_by(|(a, _), (b, _)| a.y.total_cmp(&b.y));
+            expected.sort_by(|a, b| a.y.total_cmp(&b.y));
+            assert_eq!(
+                found
+                    .into_iter()
+                    .map(|(point, _)| point)
+                    .collect::<Vec<Point>>(),
+                expected
+            );
+
+            // Each node draws its split plane inside the unit square
+            let lines = tree.lines();
+            assert_eq!(lines.len(), tree.nodes.len());
+            for line in lines {
+                let geometry::Line::PointToPoint(a, b) = line;
+                assert!(a.x == b.x || a.y == b.y);
+                for point in [a, b] {
+                    assert!((0. ..=1.).contains(&point.x) && (0. ..=1.).contains(&point.y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_strategies_with_buckets() {
+        let points: Vec<[i64; 3]> = (0..2_000)
+            .map(|_| {
+                [
+                    rand::random_range(0..1_000),
+                    rand::random_range(0..10),
+                    rand::random_range(0..100),
+                ]
+            })
+            .collect();
+        for strategy in [
+            SplitStrategy::HighestVariance,
+            SplitStrategy::SlidingMidpoint,
+        ] {
+            let mut tree = KDTree::default()
+                .with_bucket_size(8)
+                .with_split_strategy(strategy);
+            for point in points.iter() {
+                tree.add_point(*point);
+            }
+            check_order(&tree);
+            assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+            for point in points.iter().take(100) {
+                assert_eq!(tree.nearest_neighbor(point), Some((*point, &())));
+            }
+        }
+    }
+
+    /// Compares the queries using `metric` with a brute force search
+    fn check_metric<M: Metric<Point> + Copy>(metric: M) {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::from_points(&points).with_bucket_size(4);
+        for point in points.iter() {
+            tree.update_point(*point, *point);
+        }
+        let view = tree.using(metric);
+        for _ in 0..20 {
+            let target = random_point();
+            let mut distances: Vec<f32> = points
+                .iter()
+                .map(|point| metric.to_real(metric.distance(point, &target)))
+                .collect();
+            distances.sort_by(f32::total_cmp);
+
+            let (nearest, _) = view.nearest_neighbor(&target).unwrap();
+            assert_eq!(
+                metric.to_real(metric.distance(&nearest, &target)),
+                distances[0]
+            );
+            let neighbors: Vec<f32> = view
+                .k_nearest_neighbors(&target, 10)
+                .into_iter()
+                .map(|(_, _, distance)| distance)
+                .collect();
+            assert_eq!(neighbors, distances[..10]);
+            // Halfway between two distances, away from rounding errors
+            let radius = (distances[50] + distances[51]) / 2.;
+            let neighbors: Vec<f32> = view
+                .within_radius_sorted(&target, radius)
+                .into_iter()
+                .map(|(_, _, distance)| distance)
+                .collect();
+            let expected: Vec<f32> = distances
+                .iter()
+                .copied()
+                .filter(|distance| *distance <= radius)
+                .collect();
+            assert_eq!(neighbors, expected);
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        check_metric(Euclidean);
+        check_metric(Manhattan);
+        check_metric(Chebyshev);
+        check_metric(Minkowski::new(1.5));
+        check_metric(Minkowski::new(4.));
+    }
+
+    #[test]
+    fn test_default_metric() {
+        let points: Vec<[i32; 3]> = (0..1_000)
+            .map(|_| std::array::from_fn(|_| rand::random_range(-100..100)))
+            .collect();
+        let tree = KDTree::from_points(&points);
+        let target = [0, 0, 0];
+        assert_eq!(
+            tree.k_nearest_neighbor_ids(&target, 5),
+            tree.using(Euclidean).k_nearest_neighbor_ids(&target, 5)
+        );
+        assert_eq!(
+            tree.within_radius(&target, 30.),
+            tree.using(Euclidean).within_radius(&target, 30.)
+        );
+    }
+
+    #[test]
+    fn test_approx_nearest_neighbor() {
+        let points: Vec<Point> = (0..10_000).map(|_| random_point()).collect();
+        let tree = KDTree::from_points(&points);
+        let mut visited = [0; 3];
+        for _ in 0..100 {
+            let target = random_point();
+            let (nearest, _) = tree.nearest_neighbor(&target).unwrap();
+            let expected = distance(&nearest, &target);
+            for (index, epsilon) in [0., 0.5, 2.].into_iter().enumerate() {
+                let (point, _, count) = tree.approx_nearest_neighbor(&target, epsilon).unwrap();
+                assert!(distance(&point, &target) <= expected * (1. + epsilon as f32));
+                assert!(count <= points.len());
+                visited[index] += count;
+            }
+        }
+        assert!(visited[0] >= visited[1] && visited[1] >= visited[2]);
+        assert_eq!(
+            KDTree::<Point>::default().approx_nearest_neighbor(&random_point(), 1.),
+            None
+        );
+    }
+
+    #[test]
+    fn test_approx_nearest_neighbor_exact_integers() {
+        let points: Vec<[i64; 2]> = (0..1_000)
+            .map(|_| {
+                [
+                    rand::random_range(-1 << 40..1 << 40),
+                    rand::random_range(-1 << 40..1 << 40),
+                ]
+            })
+            .collect();
+        let tree = KDTree::from_points(&points);
+        for point in points.iter() {
+            let target = [point[0] + 1, point[1]];
+            let (nearest, _) = tree.nearest_neighbor(&target).unwrap();
+            let (approx, _, _) = tree.approx_nearest_neighbor(&target, 0.).unwrap();
+            assert_eq!(
+                squared_distance(&approx, &target),
+                squared_distance(&nearest, &target)
+            );
+        }
+    }
+
+    #[test]
+    fn test_approx_nearest_neighbor_budget() {
+        let points: Vec<Point> = (0..10_000).map(|_| random_point()).collect();
+        let tree = KDTree::from_points(&points);
+        let target = random_point();
+        assert_eq!(tree.approx_nearest_neighbor_bounded(&target, 0., 0), None);
+        // The path of the target point is longer than the budgets
+        for max_visited in [1, 2, 5] {
+            let (_, _, visited) = tree
+                .approx_nearest_neighbor_bounded(&target, 0., max_visited)
+                .unwrap();
+            assert_eq!(visited, max_visited);
+        }
+        // The budget of a full search gives the nearest neighbor
+        let (_, _, visited) = tree.approx_nearest_neighbor(&target, 0.).unwrap();
+        let (point, _, count) = tree
+            .approx_nearest_neighbor_bounded(&target, 0., visited)
+            .unwrap();
+        assert_eq!(count, visited);
+        assert_eq!(Some((point, &())), tree.nearest_neighbor(&target));
+    }
+
+    #[test]
+    fn test_neighbors_by_distance() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let tree = KDTree::from_points(&points).with_bucket_size(4);
+        for _ in 0..20 {
+            let target = random_point();
+            let mut expected: Vec<f32> = points
+                .iter()
+                .map(|point| distance(point, &target))
+                .collect();
+            expected.sort_by(f32::total_cmp);
+            let neighbors: Vec<(Point, f32)> = tree
+                .neighbors_by_distance(&target)
+                .map(|(point, _, distance)| (point, distance))
+                .collect();
+            assert_eq!(neighbors.len(), points.len());
+            for (point, reported) in neighbors.iter() {
+                assert_eq!(distance(point, &target), *reported);
+            }
+            let distances: Vec<f32> = neighbors.iter().map(|(_, distance)| *distance).collect();
+            assert_eq!(distances, expected);
+
+            let nearest: Vec<(Point, &(), f32)> =
+                tree.neighbors_by_distance(&target).take(10).collect();
+            assert_eq!(nearest, tree.k_nearest_neighbors(&target, 10));
+            let radius = (expected[100] + expected[101]) / 2.;
+            let count = tree
+                .neighbors_by_distance(&target)
+                .take_while(|(_, _, distance)| *distance <= radius)
+                .count();
+            assert_eq!(count, tree.within_radius(&target, radius).len());
+        }
+        assert_eq!(
+            KDTree::<Point>::default()
+                .neighbors_by_distance(&random_point())
+                .next(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_neighbors_by_distance_with_metric() {
+        let points: Vec<[i32; 2]> = (0..1_000)
+            .map(|_| [rand::random_range(-50..50), rand::random_range(-50..50)])
+            .collect();
+        let tree = KDTree::from_points(&points);
+        let target = [0, 0];
+        let mut expected: Vec<f64> = points
+            .iter()
+            .map(|point| Chebyshev.distance(point, &target))
+            .collect();
+        expected.sort_by(f64::total_cmp);
+        let distances: Vec<f64> = tree
+            .using(Chebyshev)
+            .neighbors_by_distance(&target)
+            .map(|(_, _, distance)| distance)
+            .collect();
+        assert_eq!(distances, expected);
+    }
+
+    #[test]
+    fn test_filtered_nearest_neighbors() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::default().with_bucket_size(4);
+        for (index, point) in points.iter().enumerate() {
+            tree.insert(*point, index % 3 == 0);
+        }
+        for _ in 0..20 {
+            let target = random_point();
+            let mut expected: Vec<f32> = points
+                .iter()
+                .step_by(3)
+                .map(|point| distance(point, &target))
+                .collect();
+            expected.sort_by(f32::total_cmp);
+
+            let (nearest, available) = tree
+                .nearest_neighbor_where(&target, |_, available| *available)
+                .unwrap();
+            assert!(*available);
+            assert_eq!(distance(&nearest, &target), expected[0]);
+            let neighbors = tree.k_nearest_neighbors_where(&target, 10, |_, available| *available);
+            assert!(neighbors.iter().all(|(_, available, _)| **available));
+            let distances: Vec<f32> = neighbors.iter().map(|(_, _, distance)| *distance).collect();
+            assert_eq!(distances, expected[..10]);
+
+            // No point may lie right of the target, where the filter matches nothing
+            let nearest = tree
+                .using(Manhattan)
+                .nearest_neighbor_where(&target, |point, _| point.x > target.x);
+            let expected = points
+                .iter()
+                .filter(|point| point.x > target.x)
+                .map(|point| Manhattan.distance(point, &target))
+                .min_by(f32::total_cmp);
+            assert_eq!(
+                nearest.map(|(nearest, _)| Manhattan.distance(&nearest, &target)),
+                expected
+            );
+        }
+        let target = random_point();
+        assert_eq!(tree.nearest_neighbor_where(&target, |_, _| false), None);
+        assert!(
+            tree.k_nearest_neighbors_where(&target, 10, |_, _| false)
+                .is_empty()
+        );
+        assert_eq!(
+            tree.k_nearest_neighbors_where(&target, 1_000, |_, available| *available)
+                .len(),
+            334
+        );
+    }
+
+    #[test]
+    fn test_farthest_points() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let tree = KDTree::from_points(&points).with_bucket_size(4);
+        for _ in 0..20 {
+            // Targets inside and outside of the bounding box of the points
+            let target = Point::new(random::<f32>() * 3. - 1., random::<f32>() * 3. - 1.);
+            let mut expected: Vec<f32> = points
+                .iter()
+                .map(|point| distance(point, &target))
+                .collect();
+            expected.sort_by(|a, b| b.total_cmp(a));
+
+            let (farthest, _) = tree.farthest_point(&target).unwrap();
+            assert_eq!(distance(&farthest, &target), expected[0]);
+            let distances: Vec<f32> = tree
+                .k_farthest_points(&target, 10)
+                .into_iter()
+                .map(|(_, _, distance)| distance)
+                .collect();
+            assert_eq!(distances, expected[..10]);
+
+            let (farthest, _) = tree.using(Chebyshev).farthest_point(&target).unwrap();
+            let expected = points
+                .iter()
+                .map(|point| Chebyshev.distance(point, &target))
+                .max_by(f32::total_cmp);
+            assert_eq!(Some(Chebyshev.distance(&farthest, &target)), expected);
+        }
+        assert_eq!(tree.k_farthest_points(&random_point(), 2_000).len(), 1_000);
+        assert_eq!(
+            KDTree::<Point>::default().farthest_point(&random_point()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_farthest_points_exact_integers() {
+        let points: Vec<[i64; 3]> = (0..1_000)
+            .map(|_| std::array::from_fn(|_| rand::random_range(-1 << 40..1 << 40)))
+            .collect();
+        let tree = KDTree::from_points(&points);
+        for target in points.iter().take(100) {
+            let (farthest, _) = tree.farthest_point(target).unwrap();
+            let expected = points
+                .iter()
+                .map(|point| squared_distance(point, target))
+                .max();
+            assert_eq!(Some(squared_distance(&farthest, target)), expected);
+        }
+    }
+
+    #[test]
+    fn test_lazy_deletion() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::from_points(&points).with_lazy_deletion(0.2);
+        let root = tree.nodes[tree.root_index].point;
+        assert_eq!(tree.remove_point(root), Some(()));
+        assert_eq!(tree.dead_len(), 1);
+        assert_eq!(tree.len(), 999);
+        assert!(!tree.points().contains(&root));
+        assert_ne!(
+            tree.nearest_neighbor(&root).map(|(point, _)| point),
+            Some(root)
+        );
+        assert_eq!(tree.remove_point(root), None);
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 999);
+
+        // The dead node is rebuilt away once the dead nodes exceed the threshold
+        let mut dead_lens = Vec::new();
+        for point in points.iter().filter(|point| **point != root).take(500) {
+            tree.remove_point(*point);
+            assert!(tree.dead_len() as f32 <= 0.2 * (tree.dead_len() + tree.len()) as f32);
+            dead_lens.push(tree.dead_len());
+        }
+        assert!(dead_lens.windows(2).any(|lens| lens[1] < lens[0]));
+        assert_eq!(tree.len(), 499);
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 499);
+        check_order(&tree);
+    }
+
+    #[test]
+    fn test_lazy_deletion_random_operations() {
+        for bucket_size in [1, 4] {
+            let mut points: Vec<(PointId, Point)> = Vec::new();
+            let mut tree = KDTree::default()
+                .with_bucket_size(bucket_size)
+                .with_lazy_deletion(0.5);
+            for _ in 0..5_000 {
+                let operation = rand::random_range(0..10);
+                if !points.is_empty() && operation < 4 {
+                    let (id, point) = points.swap_remove(rand::random_range(0..points.len()));
+                    assert_eq!(tree.remove(id), Some((point, ())));
+                } else if !points.is_empty() && operation < 5 {
+                    let index = rand::random_range(0..points.len());
+                    let point = random_point();
+                    assert!(tree.move_to(points[index].0, point));
+                    points[index].1 = point;
+                } else {
+                    let point = random_point();
+                    points.push((tree.add_point(point).id(), point));
+                }
+                assert_eq!(tree.len(), points.len());
+            }
+            assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+            check_order(&tree);
+            for (id, point) in points.iter() {
+                assert_eq!(tree.get(*id), Some((*point, &())));
+            }
+            for _ in 0..20 {
+                let target = random_point();
+                let expected = points
+                    .iter()
+                    .map(|(_, point)| distance(point, &target))
+                    .min_by(f32::total_cmp);
+                let nearest = tree.nearest_neighbor(&target);
+                assert_eq!(
+                    nearest.map(|(point, _)| distance(&point, &target)),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_lazy_deletion_revives_dead_leaves() {
+        let mut tree = KDTree::default().with_lazy_deletion(1.);
+        let points = [[0, 0], [-1, 0], [1, 0]];
+        for point in points {
+            tree.add_point(point);
+        }
+        tree.remove_point([0, 0]);
+        tree.remove_point([-1, 0]);
+        tree.remove_point([1, 0]);
+        assert!(tree.is_empty());
+        assert_eq!(tree.dead_len(), 1);
+        assert_eq!(tree.nearest_neighbor(&[0, 0]), None);
+
+        tree.add_point([2, 2]);
+        assert_eq!(tree.dead_len(), 0);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.nearest_neighbor(&[0, 0]), Some(([2, 2], &())));
+    }
+
+    #[test]
+    fn test_lazy_deletion_approx_nearest_neighbor() {
+        // Dead nodes on the primary path leave no bound to prune the secondary subtrees
+        let mut tree = KDTree::default().with_lazy_deletion(0.3);
+        for x in 0..10 {
+            tree.add_point([x * 10, 0]);
+        }
+        for x in 0..3 {
+            tree.remove_point([x * 10, 0]);
+        }
+        assert_eq!(
+            tree.approx_nearest_neighbor(&[-5, 0], 0.)
+                .map(|(point, _, _)| point),
+            Some([30, 0])
+        );
+
+        let mut tree = KDTree::default().with_lazy_deletion(1.);
+        tree.add_point([0, 0]);
+        tree.add_point([1, 0]);
+        tree.remove_point([0, 0]);
+        assert_eq!(
+            tree.approx_nearest_neighbor(&[-1, 0], 0.)
+                .map(|(point, _, _)| point),
+            Some([1, 0])
+        );
+
+        let mut points: Vec<[i32; 2]> = (0..1_000)
+            .map(|_| [rand::random_range(0..100), rand::random_range(0..100)])
+            .collect();
+        let mut tree = KDTree::from_points(&points).with_lazy_deletion(0.5);
+        while !points.is_empty() {
+            let point = points.swap_remove(rand::random_range(0..points.len()));
+            tree.remove_point(point);
+            let target = [rand::random_range(-10..110), rand::random_range(-10..110)];
+            let expected = points
+                .iter()
+                .map(|point| squared_distance(point, &target))
+                .min();
+            let approx = tree.approx_nearest_neighbor(&target, 0.);
+            assert_eq!(
+                approx.map(|(point, _, _)| squared_distance(&point, &target)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_min() {
+        let points: Vec<[i32; 3]> = (0..1_000)
+            .map(|_| std::array::from_fn(|_| rand::random_range(-20..20)))
+            .collect();
+        let mut tree = KDTree::from_points(&points).with_bucket_size(3);
+        for axis in 0..3 {
+            let min = points.iter().map(|point| point[axis]).min();
+            let max = points.iter().map(|point| point[axis]).max();
+            assert_eq!(tree.find_min(axis).map(|(point, _)| point[axis]), min);
+            assert_eq!(tree.find_max(axis).map(|(point, _)| point[axis]), max);
+        }
+        for point in points.iter() {
+            tree.remove_point(*point);
+        }
+        assert_eq!(tree.find_min(0), None);
+        assert_eq!(tree.find_max(0), None);
+    }
+
+    #[test]
+    fn test_deletion_by_replacement() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::default();
+        let ids: Vec<PointId> = points
+            .iter()
+            .map(|point| tree.add_point(*point).id())
+            .collect();
+
+        // The root keeps its node and no subtree is rebuilt
+        let root_index = tree.root_index;
+        let root = tree.nodes[root_index].point;
+        tree.remove_point(root);
+        assert_eq!(tree.root_index, root_index);
+        assert_ne!(tree.nodes[root_index].point, root);
+        for _ in 0..200 {
+            let point = tree.points()[rand::random_range(0..tree.len())];
+            tree.remove_point(point);
+            assert_eq!(tree.nodes.len(), tree.len());
+        }
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 799);
+        check_order(&tree);
+        let remaining = tree.points();
+        for (id, point) in ids.iter().zip(points.iter()) {
+            match tree.get(*id) {
+                Some((moved, _)) => assert_eq!(moved, *point),
+                None => assert!(!remaining.contains(point)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_deletion_by_replacement_with_duplicates() {
+        for bucket_size in [1, 2] {
+            let mut points: Vec<[i32; 2]> = (0..1_000)
+                .map(|_| [rand::random_range(0..8), rand::random_range(0..8)])
+                .collect();
+            let mut tree = KDTree::from_points(&points).with_bucket_size(bucket_size);
+            while !points.is_empty() {
+                let point = points.swap_remove(rand::random_range(0..points.len()));
+                assert_eq!(tree.remove_point(point), Some(()));
+                assert_eq!(tree.len(), points.len());
+                if !points.is_empty() && points.len().is_multiple_of(50) {
+                    assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+                    check_order(&tree);
+                    let mut remaining = tree.points();
+                    remaining.sort();
+                    let mut expected = points.clone();
+                    expected.sort();
+                    assert_eq!(remaining, expected);
+                }
+            }
+            assert!(tree.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let point = random_point();
+        let mut tree = KDTree::default();
+        let first = tree.insert(point, "first");
+        assert!(first.is_inserted());
+        let second = tree.insert(point, "second");
+        assert!(second.is_inserted());
+        assert_ne!(first.id(), second.id());
+        assert_eq!(tree.count(&point), 2);
+
+        let mut tree = KDTree::default().with_duplicate_policy(DuplicatePolicy::Reject);
+        let first = tree.insert(point, "first").id();
+        assert_eq!(
+            tree.insert(point, "second"),
+            Insertion::Rejected(first, "second")
+        );
+        assert_eq!(tree.get(first), Some((point, &"first")));
+        assert_eq!(tree.len(), 1);
+
+        let mut tree = KDTree::default().with_duplicate_policy(DuplicatePolicy::Replace);
+        let first = tree.insert(point, "first").id();
+        assert_eq!(
+            tree.insert(point, "second"),
+            Insertion::Replaced(first, "first")
+        );
+        assert_eq!(tree.get(first), Some((point, &"second")));
+        assert_eq!(tree.remove_all(point), vec!["second"]);
+        assert!(tree.is_empty());
+        assert_eq!(tree.count(&point), 0);
+    }
+
+    #[test]
+    fn test_duplicates_on_split_planes() {
+        // Integer coordinates make many points lie exactly on the split planes of the nodes
+        let grid: Vec<[i32; 2]> = (0..5).flat_map(|x| (0..5).map(move |y| [x, y])).collect();
+        for bucket_size in [1, 4] {
+            let mut points: Vec<[i32; 2]> = (0..500)
+                .map(|_| [rand::random_range(0..5), rand::random_range(0..5)])
+                .collect();
+            let mut tree = KDTree::default().with_bucket_size(bucket_size);
+            for point in points.iter() {
+                assert!(tree.add_point(*point).is_inserted());
+            }
+            for point in grid.iter() {
+                let expected = points.iter().filter(|other| *other == point).count();
+                assert_eq!(tree.count(point), expected);
+            }
+            // Removes all the copies of the split point of the root, then of random points
+            let mut targets = vec![tree.nodes[tree.root_index].point];
+            targets.extend((0..10).map(|_| grid[rand::random_range(0..grid.len())]));
+            for target in targets {
+                let expected = points.iter().filter(|point| **point == target).count();
+                assert_eq!(tree.remove_all(target).len(), expected);
+                points.retain(|point| *point != target);
+                assert_eq!(tree.count(&target), 0);
+                assert_eq!(tree.len(), points.len());
+                if !points.is_empty() {
+                    assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+                }
+                check_order(&tree);
+            }
+
+            let mut tree = KDTree::default()
+                .with_bucket_size(bucket_size)
+                .with_duplicate_policy(DuplicatePolicy::Replace);
+            for (index, point) in points.iter().enumerate() {
+                tree.insert(*point, index);
+            }
+            for point in grid.iter() {
+                let last = points.iter().rposition(|other| other == point);
+                assert_eq!(tree.count(point), last.map_or(0, |_| 1));
+                assert_eq!(
+                    tree.nearest_neighbor(point)
+                        .filter(|(nearest, _)| nearest == point),
+                    last.as_ref().map(|index| (*point, index))
+                );
+            }
+            check_order(&tree);
+        }
+    }
+
+    #[test]
+    fn test_grouped_duplicates() {
+        let point = [5, 5];
+        let mut tree = KDTree::default().with_lazy_deletion(0.5);
+        let ids: Vec<PointId> = (0..200_000).map(|_| tree.add_point(point).id()).collect();
+        assert_eq!(tree.depth(), 1);
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!(tree.count(&point), 200_000);
+
+        let mut grid = Vec::new();
+        for _ in 0..2_000 {
+            let other = [rand::random_range(0..10), rand::random_range(0..10)];
+            tree.add_point(other);
+            grid.push(other);
+        }
+        let copies = 200_000 + grid.iter().filter(|other| **other == point).count();
+        assert!(tree.depth() <= max_depth(tree.len()) + 2);
+        assert_eq!(tree.count(&point), copies);
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), 202_000);
+
+        // Removing the point of the node promotes a grouped point rather than killing the node
+        assert_eq!(tree.remove(ids[0]), Some((point, ())));
+        assert_eq!(tree.remove(ids[1]), Some((point, ())));
+        assert_eq!(tree.dead_len(), 0);
+        assert_eq!(tree.get(ids[2]), Some((point, &())));
+        assert_eq!(tree.count(&point), copies - 2);
+
+        assert_eq!(tree.remove_all(point).len(), copies - 2);
+        assert_eq!(tree.count(&point), 0);
+        assert_eq!(tree.get(ids[2]), None);
+        grid.retain(|other| *other != point);
+        assert_eq!(tree.len(), grid.len());
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), grid.len());
+        check_order(&tree);
+        for other in grid.iter() {
+            assert_eq!(tree.nearest_neighbor(other), Some((*other, &())));
+        }
+    }
+
+    #[test]
+    fn test_duplicate_policies_on_moves() {
+        let mut tree = KDTree::default().with_duplicate_policy(DuplicatePolicy::Reject);
+        let a = tree.insert([0, 0], "a").id();
+        let b = tree.insert([1, 1], "b").id();
+        tree.insert([2, 2], "c");
+        assert!(!tree.move_to(b, [0, 0]));
+        assert!(!tree.update_point([2, 2], [0, 0]));
+        assert_eq!(tree.count(&[0, 0]), 1);
+        assert_eq!(tree.get(a), Some(([0, 0], &"a")));
+        assert_eq!(tree.get(b), Some(([1, 1], &"b")));
+        assert!(tree.move_to(a, [0, 0]));
+        assert!(tree.move_to(b, [3, 3]));
+        assert_eq!(tree.len(), 3);
+
+        let mut tree = KDTree::default().with_duplicate_policy(DuplicatePolicy::Replace);
+        let a = tree.insert([0, 0], "a").id();
+        let b = tree.insert([1, 1], "b").id();
+        let c = tree.insert([2, 2], "c").id();
+        assert!(tree.move_to(b, [0, 0]));
+        assert_eq!(tree.get(a), None);
+        assert_eq!(tree.get(b), Some(([0, 0], &"b")));
+        assert!(tree.update_point([2, 2], [0, 0]));
+        assert_eq!(tree.get(b), None);
+        assert_eq!(tree.get(c), Some(([0, 0], &"c")));
+        assert_eq!(tree.count(&[0, 0]), 1);
+        assert_eq!(tree.len(), 1);
+
+        // Random moves onto the points of a grid keep at most one point per position
+        for policy in [DuplicatePolicy::Reject, DuplicatePolicy::Replace] {
+            let mut tree = KDTree::default().with_duplicate_policy(policy);
+            let mut ids: Vec<PointId> = (0..200)
+                .filter_map(|_| {
+                    let point = [rand::random_range(0..10), rand::random_range(0..10)];
+                    let insertion = tree.add_point(point);
+                    insertion.is_inserted().then(|| insertion.id())
+                })
+                .collect();
+            for _ in 0..500 {
+                let id = ids[rand::random_range(0..ids.len())];
+                let new = [rand::random_range(0..10), rand::random_range(0..10)];
+                let occupied = tree.count(&new) > 0 && tree.get(id).unwrap().0 != new;
+                let len = tree.len();
+                let moved = tree.move_to(id, new);
+                assert_eq!(moved, policy == DuplicatePolicy::Replace || !occupied);
+                assert_eq!(tree.len(), len - usize::from(moved && occupied));
+                assert_eq!(tree.count(&new), 1);
+                ids.retain(|id| tree.get(*id).is_some());
+            }
+            let mut points = tree.points();
+            points.sort();
+            points.dedup();
+            assert_eq!(points.len(), tree.len());
+            check_order(&tree);
+        }
+    }
+
+    #[test]
+    fn test_fallible_mutations() {
+        let points: Vec<Point> = (0..100).map(|_| random_point()).collect();
+        let mut tree = KDTree::from_points(&points).with_duplicate_policy(DuplicatePolicy::Reject);
+        for invalid in [
+            Point::new(f32::NAN, 0.5),
+            Point::new(0.5, f32::INFINITY),
+            Point::new(f32::NEG_INFINITY, 0.5),
+        ] {
+            assert_eq!(
+                tree.try_add_point(invalid),
+                Err(KDTreeError::NonFiniteCoordinate)
+            );
+            assert_eq!(
+                tree.try_remove_point(invalid),
+                Err(KDTreeError::NonFiniteCoordinate)
+            );
+        }
+        assert_eq!(tree.len(), 100);
+
+        let id = tree.try_add_point(Point::new(2., 2.)).unwrap();
+        assert_eq!(
+            tree.try_add_point(Point::new(2., 2.)),
+            Err(KDTreeError::Duplicate(id))
+        );
+        let other = tree.nearest_neighbor_id(&points[0]).unwrap();
+        assert_eq!(
+            tree.try_move_to(other, Point::new(2., 2.)),
+            Err(KDTreeError::Duplicate(id))
+        );
+        assert_eq!(
+            tree.try_move_to(id, Point::new(f32::NAN, 2.)),
+            Err(KDTreeError::NonFiniteCoordinate)
+        );
+        assert_eq!(tree.try_move_to(id, Point::new(2., 3.)), Ok(()));
+        assert_eq!(tree.try_remove(id), Ok((Point::new(2., 3.), ())));
+        assert_eq!(tree.try_remove(id), Err(KDTreeError::NotFound));
+        assert_eq!(
+            tree.try_move_to(id, Point::new(2., 2.)),
+            Err(KDTreeError::NotFound)
+        );
+
+        assert_eq!(tree.try_remove_point(points[0]), Ok((points[0], ())));
+        assert_eq!(tree.try_remove_point(points[0]), Err(KDTreeError::NotFound));
+        assert_eq!(tree.len(), 99);
+        check_order(&tree);
+    }
+
+    #[test]
+    fn test_extend() {
+        for bucket_size in [1, 8] {
+            let mut points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+            let mut tree = KDTree::from_points(&points).with_bucket_size(bucket_size);
+            for _ in 0..5 {
+                // Sorted batches would unbalance the tree with single insertions
+                let mut batch: Vec<Point> = (0..500).map(|_| random_point()).collect();
+                batch.sort_by(|a, b| a.x.total_cmp(&b.x));
+                let ids: Vec<PointId> = tree.extend(&batch).iter().map(Insertion::id).collect();
+                points.extend(batch.iter());
+                assert_eq!(tree.len(), points.len());
+                assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+                check_order(&tree);
+                assert!(tree.depth() <= max_depth(tree.len().div_ceil(bucket_size)) + 1);
+                for (id, point) in ids.iter().zip(batch.iter()) {
+                    assert_eq!(tree.get(*id), Some((*point, &())));
+                }
+            }
+            let target = random_point();
+            let expected = points
+                .iter()
+                .map(|point| distance(point, &target))
+                .min_by(f32::total_cmp)
+                .unwrap();
+            let (nearest, _) = tree.nearest_neighbor(&target).unwrap();
+            assert_eq!(distance(&nearest, &target), expected);
+        }
+
+        // Batches revive or rebuild the dead nodes of lazy deletion
+        let mut points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::from_points(&points).with_lazy_deletion(0.5);
+        for point in points.drain(..300) {
+            tree.remove_point(point);
+        }
+        let batch: Vec<Point> = (0..300).map(|_| random_point()).collect();
+        tree.extend(&batch);
+        points.extend(batch);
+        assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+        assert_eq!(
+            tree.nodes.values().filter(|node| node.is_dead()).count(),
+            tree.dead_len()
+        );
+        check_order(&tree);
+
+        let mut tree = KDTree::default().with_duplicate_policy(DuplicatePolicy::Reject);
+        let point = random_point();
+        let insertions = tree.extend(&[point, point]);
+        assert!(insertions[0].is_inserted());
+        assert_eq!(insertions[1], Insertion::Rejected(insertions[0].id(), ()));
+    }
+
+    #[test]
+    fn test_remove_many() {
+        for (bucket_size, max_dead_fraction) in [(1, None), (4, None), (1, Some(0.25))] {
+            let mut points: Vec<[i32; 2]> = (0..2_000)
+                .map(|_| [rand::random_range(0..30), rand::random_range(0..30)])
+                .collect();
+            let mut tree = KDTree::default().with_bucket_size(bucket_size);
+            if let Some(max_dead_fraction) = max_dead_fraction {
+                tree = tree.with_lazy_deletion(max_dead_fraction);
+            }
+            tree.extend(&points);
+            while !points.is_empty() {
+                // Batches mix points of the tree, some of them duplicated, and missing points
+                let len = rand::random_range(1..=points.len().min(300));
+                let mut batch: Vec<[i32; 2]> = (0..len)
+                    .map(|_| points.swap_remove(rand::random_range(0..points.len())))
+                    .collect();
+                batch.push([-1, -1]);
+                let values = tree.remove_many(&batch);
+                assert_eq!(values.iter().filter(|value| value.is_some()).count(), len);
+                assert_eq!(values[len], None);
+                assert_eq!(tree.len(), points.len());
+                if !points.is_empty() {
+                    assert_eq!(check_sizes(&tree, Some(tree.root_index)), points.len());
+                }
+                check_order(&tree);
+                let mut remaining = tree.points();
+                remaining.sort();
+                let mut expected = points.clone();
+                expected.sort();
+                assert_eq!(remaining, expected);
+            }
+            assert!(tree.is_empty());
+        }
+
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::default();
+        let ids: Vec<PointId> = points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| tree.insert(*point, index).id())
+            .collect();
+        let values = tree.remove_many(&points[..100]);
+        assert_eq!(values, (0..100).map(Some).collect::<Vec<_>>());
+        for (index, id) in ids.iter().enumerate() {
+            let expected = (index >= 100).then_some((points[index], &index));
+            assert_eq!(tree.get(*id), expected);
+        }
+    }
+
+    #[test]
+    fn test_batches_of_duplicates() {
+        for max_dead_fraction in [None, Some(0.5)] {
+            let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+            let mut tree = KDTree::from_points(&points);
+            if let Some(max_dead_fraction) = max_dead_fraction {
+                tree = tree.with_lazy_deletion(max_dead_fraction);
+            }
+            let (old, new) = (points[0], random_point());
+            let mut batch = vec![old; 100_000];
+            batch.extend(vec![new; 100_000]);
+            tree.extend(&batch);
+            assert_eq!(tree.count(&old), 100_001);
+            assert_eq!(tree.count(&new), 100_000);
+            assert!(tree.depth() <= max_depth(1_000) + 2);
+            assert_eq!(check_sizes(&tree, Some(tree.root_index)), 201_000);
+            check_order(&tree);
+
+            let values = tree.remove_many(&vec![old; 100_001]);
+            assert!(values.iter().all(Option::is_some));
+            assert_eq!(tree.count(&old), 0);
+            let values = tree.remove_many(&vec![new; 50_000]);
+            assert!(values.iter().all(Option::is_some));
+            assert_eq!(tree.count(&new), 50_000);
+            assert_eq!(check_sizes(&tree, Some(tree.root_index)), 50_999);
+            check_order(&tree);
         }
     }
 }
@@ -1,6 +1,16 @@
 mod app;
+mod arena;
 mod geometry;
 mod kdtree;
+mod metric;
+mod point;
+mod static_kdtree;
 pub use app::App;
 pub use geometry::Geometry;
-pub use kdtree::KDTree;
+pub use kdtree::{
+    DuplicatePolicy, Insertion, KDTree, KDTreeError, MetricView, NeighborsByDistance, PointId,
+    SplitStrategy,
+};
+pub use metric::{Chebyshev, Euclidean, Manhattan, Metric, Minkowski};
+pub use point::{Float, KdPoint, Scalar, distance, squared_distance};
+pub use static_kdtree::{StaticKDTree, StaticMetricView};
@@ -1,9 +1,5 @@
-mod app;
-mod geometry;
-mod kdtree;
-
-use app::App;
 use iced::Theme;
+use kdtree_iced::App;
 
 fn main() -> iced::Result {
     iced::application("Iced Visualization - KDTree", App::update, App::view)
@@ -0,0 +1,234 @@
+use std::fmt::Debug;
+
+use crate::point::{Float, KdPoint, Real, Scalar, Squared, squared_distance};
+
+/// A distance between points used by the queries of a `KDTree`, see
+/// [`KDTree::using`](crate::KDTree::using).
+///
+/// Searches only compare values of `Metric::Distance`, a reduced distance which must be ordered
+/// like the actual distances but can skip a costly final step such as the square root of the
+/// [`Euclidean`] distance. Only the distances reported to the caller are converted with
+/// [`Metric::to_real`].
+pub trait Metric<P: KdPoint> {
+    /// Type of the reduced distances compared during searches
+    type Distance: Copy + PartialOrd + Debug;
+
+    /// Returns the reduced distance between two points
+    fn distance(&self, a: &P, b: &P) -> Self::Distance;
+
+    /// Returns the reduced distance between `point` and the axis-aligned plane of the points whose
+    /// coordinate along `axis` is `value`. It must be a lower bound of the distance between
+    /// `point` and any point on the other side of the plane, so that the subtree on the other side
+    /// of a split plane can be skipped.
+    fn plane_distance(&self, point: &P, axis: usize, value: P::Scalar) -> Self::Distance;
+
+    /// Combines two reduced distances along different axes, so that the distance between two
+    /// points is the combination of their [`Metric::plane_distance`] along every axis. It is
+    /// used to bound the distance between a point and an axis-aligned box.
+    fn combine(&self, a: Self::Distance, b: Self::Distance) -> Self::Distance;
+
+    /// Converts a reduced distance into the reported distance
+    fn to_real(&self, distance: Self::Distance) -> Real<P>;
+
+    /// Converts a reported distance into the greatest reduced distance `d` such that
+    /// `self.to_real(d) <= real`
+    fn distance_bound(&self, real: Real<P>) -> Self::Distance;
+}
+
+/// Euclidean distance, the default metric:
+///
+/// $$
+/// d(A, B) = \sqrt{\sum_{i = 0}^{K - 1} (A_i - B_i)^2}
+/// $$
+///
+/// Squared distances are compared, which is exact for integer coordinates.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Euclidean;
+
+impl<P: KdPoint> Metric<P> for Euclidean {
+    type Distance = Squared<P>;
+
+    fn distance(&self, a: &P, b: &P) -> Squared<P> {
+        squared_distance(a, b)
+    }
+
+    fn plane_distance(&self, point: &P, axis: usize, value: P::Scalar) -> Squared<P> {
+        point.coord(axis).squared_difference(value)
+    }
+
+    fn combine(&self, a: Squared<P>, b: Squared<P>) -> Squared<P> {
+        P::Scalar::add_squared(a, b)
+    }
+
+    fn to_real(&self, distance: Squared<P>) -> Real<P> {
+        P::Scalar::sqrt(distance)
+    }
+
+    fn distance_bound(&self, real: Real<P>) -> Squared<P> {
+        P::Scalar::squared(real)
+    }
+}
+
+/// Manhattan (taxicab) distance:
+///
+/// $$
+/// d(A, B) = \sum_{i = 0}^{K - 1} |A_i - B_i|
+/// $$
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Manhattan;
+
+impl<P: KdPoint> Metric<P> for Manhattan {
+    type Distance = Real<P>;
+
+    fn distance(&self, a: &P, b: &P) -> Real<P> {
+        (0..P::dim()).fold(Real::<P>::default(), |sum, axis| {
+            sum + a.coord(axis).abs_difference(b.coord(axis))
+        })
+    }
+
+    fn plane_distance(&self, point: &P, axis: usize, value: P::Scalar) -> Real<P> {
+        point.coord(axis).abs_difference(value)
+    }
+
+    fn combine(&self, a: Real<P>, b: Real<P>) -> Real<P> {
+        a + b
+    }
+
+    fn to_real(&self, distance: Real<P>) -> Real<P> {
+        distance
+    }
+
+    fn distance_bound(&self, real: Real<P>) -> Real<P> {
+        real
+    }
+}
+
+/// Chebyshev (maximum) distance:
+///
+/// $$
+/// d(A, B) = \max_{0 \leq i < K} |A_i - B_i|
+/// $$
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Chebyshev;
+
+impl<P: KdPoint> Metric<P> for Chebyshev {
+    type Distance = Real<P>;
+
+    fn distance(&self, a: &P, b: &P) -> Real<P> {
+        (0..P::dim()).fold(Real::<P>::default(), |max, axis| {
+            let delta = a.coord(axis).abs_difference(b.coord(axis));
+            if delta > max { delta } else { max }
+        })
+    }
+
+    fn plane_distance(&self, point: &P, axis: usize, value: P::Scalar) -> Real<P> {
+        point.coord(axis).abs_difference(value)
+    }
+
+    fn combine(&self, a: Real<P>, b: Real<P>) -> Real<P> {
+        if a > b { a } else { b }
+    }
+
+    fn to_real(&self, distance: Real<P>) -> Real<P> {
+        distance
+    }
+
+    fn distance_bound(&self, real: Real<P>) -> Real<P> {
+        real
+    }
+}
+
+/// Minkowski distance of order $p \geq 1$, which generalizes the Manhattan ($p = 1$) and the
+/// Euclidean ($p = 2$) distances:
+///
+/// $$
+/// d(A, B) = \left(\sum_{i = 0}^{K - 1} |A_i - B_i|^p\right)^{1/p}
+/// $$
+///
+/// The sums of the powers are compared, so the root of order $p$ is only taken to report a
+/// distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Minkowski {
+    /// Order of the distance
+    p: f64,
+}
+
+impl Minkowski {
+    /// Creates the Minkowski distance of order `p`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `p` is lower than `1`, where the distance is not a metric, or if `p` is infinite,
+    /// whose limit is the [`Chebyshev`] distance.
+    pub fn new(p: f64) -> Self {
+        assert!(p >= 1., "order of a Minkowski distance must be at least 1");
+        assert!(
+            p.is_finite(),
+            "order of a Minkowski distance must be finite"
+        );
+        Self { p }
+    }
+}
+
+impl<P: KdPoint> Metric<P> for Minkowski {
+    type Distance = Real<P>;
+
+    fn distance(&self, a: &P, b: &P) -> Real<P> {
+        (0..P::dim()).fold(Real::<P>::default(), |sum, axis| {
+            sum + self.plane_distance(a, axis, b.coord(axis))
+        })
+    }
+
+    fn plane_distance(&self, point: &P, axis: usize, value: P::Scalar) -> Real<P> {
+        let delta = point.coord(axis).abs_difference(value);
+        delta.powf(Real::<P>::from_f64(self.p))
+    }
+
+    fn combine(&self, a: Real<P>, b: Real<P>) -> Real<P> {
+        a + b
+    }
+
+    fn to_real(&self, distance: Real<P>) -> Real<P> {
+        distance.powf(Real::<P>::from_f64(1. / self.p))
+    }
+
+    fn distance_bound(&self, real: Real<P>) -> Real<P> {
+        real.powf(Real::<P>::from_f64(self.p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distances() {
+        let a: [f64; 2] = [1., -2.];
+        let b: [f64; 2] = [4., 2.];
+        assert_eq!(Euclidean.distance(&a, &b), 25.);
+        assert_eq!(Metric::<[f64; 2]>::to_real(&Euclidean, 25.), 5.);
+        assert_eq!(Manhattan.distance(&a, &b), 7.);
+        assert_eq!(Chebyshev.distance(&a, &b), 4.);
+        assert_eq!(Minkowski::new(1.).distance(&a, &b), 7.);
+        assert!((Minkowski::new(2.).distance(&a, &b) - 25.).abs() < 1e-12);
+        let cube = Minkowski::new(3.);
+        assert!((cube.distance(&a, &b) - 91.).abs() < 1e-12);
+        assert!((Metric::<[f64; 2]>::to_real(&cube, 91.) - 91_f64.cbrt()).abs() < 1e-12);
+        assert!((Metric::<[f64; 2]>::distance_bound(&cube, 91_f64.cbrt()) - 91.).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be finite")]
+    fn test_infinite_minkowski_order() {
+        Minkowski::new(f64::INFINITY);
+    }
+
+    #[test]
+    fn test_integer_distances() {
+        let a: [i64; 3] = [i64::MIN, 0, 3];
+        let b: [i64; 3] = [i64::MAX, 0, -1];
+        assert_eq!(Euclidean.distance(&a, &b), (u64::MAX as u128).pow(2) + 16);
+        assert_eq!(Chebyshev.distance(&a, &b), u64::MAX as f64);
+        assert_eq!(Manhattan.plane_distance(&a, 2, 10), 7.);
+    }
+}
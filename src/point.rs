@@ -0,0 +1,223 @@
+use iced::Point;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::ops::{Add, Div, Mul};
+
+/// A numeric type used for the coordinates of a [`KdPoint`]
+///
+/// Distances are compared through their squares computed in `Scalar::Squared`, which is exact for
+/// integers, and the square root is only taken to report a distance in `Scalar::Real`.
+pub trait Scalar: Copy + PartialOrd + Debug {
+    /// Type of squared distances, wide enough to hold exact values for integers
+    type Squared: Copy + PartialOrd + Debug + Default;
+    /// Floating point type used to report distances
+    type Real: Float;
+
+    /// Returns the square of the difference between `self` and `other`
+    fn squared_difference(self, other: Self) -> Self::Squared;
+
+    /// Returns the sum of two squared distances, which saturates at the greatest squared distance
+    /// for integers rather than wrapping around
+    fn add_squared(a: Self::Squared, b: Self::Squared) -> Self::Squared;
+
+    /// Returns the absolute value of the difference between `self` and `other`
+    fn abs_difference(self, other: Self) -> Self::Real;
+
+    /// Returns the square root of a squared distance
+    fn sqrt(squared: Self::Squared) -> Self::Real;
+
+    /// Returns the greatest squared distance lower than or equal to `real * real`, so that
+    /// $d^2 \leq \text{squared}(r) \iff d \leq r$
+    fn squared(real: Self::Real) -> Self::Squared;
+
+    /// Returns a total ordering between `self` and `other`
+    fn total_cmp(&self, other: &Self) -> Ordering;
+
+    /// Converts the coordinate to `f64`, used for statistics such as means and variances
+    fn to_f64(self) -> f64;
+
+    /// Returns `false` if the coordinate is NaN or infinite, which cannot be ordered along an
+    /// axis
+    fn is_finite(self) -> bool;
+}
+
+/// A floating point type used to report distances
+pub trait Float:
+    Copy + PartialOrd + Debug + Default + Add<Output = Self> + Mul<Output = Self> + Div<Output = Self>
+{
+    /// Returns `self` raised to the power `exponent`
+    fn powf(self, exponent: Self) -> Self;
+
+    /// Converts a `f64` into the floating point type, with a loss of precision for `f32`
+    fn from_f64(value: f64) -> Self;
+}
+
+macro_rules! impl_float {
+    ($($float:ty),*) => {
+        $(
+            impl Float for $float {
+                fn powf(self, exponent: Self) -> Self {
+                    <$float>::powf(self, exponent)
+                }
+
+                fn from_f64(value: f64) -> Self {
+                    value as $float
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32, f64);
+
+macro_rules! impl_float_scalar {
+    ($($float:ty),*) => {
+        $(
+            impl Scalar for $float {
+                type Squared = $float;
+                type Real = $float;
+
+                fn squared_difference(self, other: Self) -> Self::Squared {
+                    let delta = self - other;
+                    delta * delta
+                }
+
+                fn add_squared(a: Self::Squared, b: Self::Squared) -> Self::Squared {
+                    a + b
+                }
+
+                fn abs_difference(self, other: Self) -> Self::Real {
+                    (self - other).abs()
+                }
+
+                fn sqrt(squared: Self::Squared) -> Self::Real {
+                    squared.sqrt()
+                }
+
+                fn squared(real: Self::Real) -> Self::Squared {
+                    real * real
+                }
+
+                fn total_cmp(&self, other: &Self) -> Ordering {
+                    <$float>::total_cmp(self, other)
+                }
+
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn is_finite(self) -> bool {
+                    <$float>::is_finite(self)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_integer_scalar {
+    ($($integer:ty),*) => {
+        $(
+            impl Scalar for $integer {
+                type Squared = u128;
+                type Real = f64;
+
+                fn squared_difference(self, other: Self) -> Self::Squared {
+                    let delta = self.abs_diff(other) as u128;
+                    delta * delta
+                }
+
+                fn add_squared(a: Self::Squared, b: Self::Squared) -> Self::Squared {
+                    a.saturating_add(b)
+                }
+
+                fn abs_difference(self, other: Self) -> Self::Real {
+                    self.abs_diff(other) as f64
+                }
+
+                fn sqrt(squared: Self::Squared) -> Self::Real {
+                    (squared as f64).sqrt()
+                }
+
+                fn squared(real: Self::Real) -> Self::Squared {
+                    (real * real).floor() as u128
+                }
+
+                fn total_cmp(&self, other: &Self) -> Ordering {
+                    self.cmp(other)
+                }
+
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn is_finite(self) -> bool {
+                    true
+                }
+            }
+        )*
+    };
+}
+
+impl_float_scalar!(f32, f64);
+impl_integer_scalar!(i32, i64);
+
+/// A point of a $K$-dimensional space which can be stored into a `KDTree`
+pub trait KdPoint: Copy + PartialEq + Debug {
+    /// Type of the coordinates
+    type Scalar: Scalar;
+
+    /// Returns the number of dimensions $K$ of the space
+    fn dim() -> usize;
+
+    /// Returns the coordinate of the point along the specified `axis` where
+    /// $0 \leq \text{axis} < K$
+    fn coord(&self, axis: usize) -> Self::Scalar;
+}
+
+impl KdPoint for Point {
+    type Scalar = f32;
+
+    fn dim() -> usize {
+        2
+    }
+
+    fn coord(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            _ => self.y,
+        }
+    }
+}
+
+impl<T: Scalar, const K: usize> KdPoint for [T; K] {
+    type Scalar = T;
+
+    fn dim() -> usize {
+        K
+    }
+
+    fn coord(&self, axis: usize) -> T {
+        self[axis]
+    }
+}
+
+/// Squared distance type of a point
+pub type Squared<P> = <<P as KdPoint>::Scalar as Scalar>::Squared;
+/// Real distance type of a point
+pub type Real<P> = <<P as KdPoint>::Scalar as Scalar>::Real;
+
+/// Returns the squared euclidian distance between two points:
+///
+/// $$
+/// d(A, B)^2 = \sum_{i = 0}^{K - 1} (A_i - B_i)^2
+/// $$
+pub fn squared_distance<P: KdPoint>(a: &P, b: &P) -> Squared<P> {
+    (0..P::dim()).fold(Squared::<P>::default(), |sum, axis| {
+        P::Scalar::add_squared(sum, a.coord(axis).squared_difference(b.coord(axis)))
+    })
+}
+
+/// Returns the euclidian distance between two points
+pub fn distance<P: KdPoint>(a: &P, b: &P) -> Real<P> {
+    P::Scalar::sqrt(squared_distance(a, b))
+}
@@ -0,0 +1,271 @@
+use crate::kdtree::KDTree;
+use crate::metric::{Euclidean, Metric};
+use crate::point::{KdPoint, Scalar};
+use iced::Point;
+
+/// An immutable KDTree for read-only workloads, built once from a `KDTree` or from points.
+///
+/// The nodes are stored in a flat array in Eytzinger (breadth-first) order: the root is at index
+/// `0` and the children of the node at index $i$ are at indices $2i + 1$ and $2i + 2$. The tree is
+/// complete, i.e. all levels are full except the last one which is filled from the left, so no
+/// child index nor split axis is stored: the split axis of a node is given by its depth. Queries
+/// only walk contiguous memory, without following pointers.
+#[derive(Debug)]
+pub struct StaticKDTree<P: KdPoint = Point, V = ()> {
+    /// Points of the nodes in Eytzinger order
+    points: Vec<P>,
+    /// Values associated with the points, in the same order
+    values: Vec<V>,
+}
+
+impl<P: KdPoint> StaticKDTree<P> {
+    /// Builds a `StaticKDTree` from points in $O(n \cdot \log_2(n))$ where $n$ is the number of
+    /// points. The depth of the tree is exactly $\lceil \log_2(n + 1) \rceil$.
+    pub fn from_points(points: &[P]) -> Self {
+        StaticKDTree::from_entries(points.iter().map(|point| (*point, ())).collect())
+    }
+}
+
+impl<P: KdPoint, V> StaticKDTree<P, V> {
+    /// Builds a `StaticKDTree` from points and their values. See [`StaticKDTree::from_points`].
+    pub fn from_entries(entries: Vec<(P, V)>) -> Self {
+        let (points, values): (Vec<P>, Vec<V>) = entries.into_iter().unzip();
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        let mut layout = vec![0; points.len()];
+        build_layout(&points, &mut order, &mut layout, 0, 0);
+
+        let mut values: Vec<Option<V>> = values.into_iter().map(Some).collect();
+        StaticKDTree {
+            points: layout.iter().map(|index| points[*index]).collect(),
+            values: layout
+                .iter()
+                .map(|index| values[*index].take().expect("each entry is laid out once"))
+                .collect(),
+        }
+    }
+
+    /// Returns the number of points in the tree
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Returns `true` if the tree contains no points
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Returns the depth of the tree, i.e. the number of nodes on the longest path from the root
+    /// to a leaf.
+    pub fn depth(&self) -> usize {
+        (usize::BITS - self.len().leading_zeros()) as usize
+    }
+
+    /// Returns an iterator over the points of the tree and their values, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (P, &V)> {
+        self.points.iter().copied().zip(self.values.iter())
+    }
+
+    /// Returns a view of the tree whose queries measure distances with the specified `metric`
+    /// instead of the [`Euclidean`] distance.
+    pub fn using<M: Metric<P>>(&self, metric: M) -> StaticMetricView<'_, P, V, M> {
+        StaticMetricView { tree: self, metric }
+    }
+
+    /// Finds the nearest neighbor of the specified `point` and returns it with its value.
+    pub fn nearest_neighbor(&self, point: &P) -> Option<(P, &V)> {
+        self.using(Euclidean).nearest_neighbor(point)
+    }
+
+    /// Searchs the nearest neighbor recursively in the subtree starting from `node_index` and
+    /// updates `best` with the index and the distance of the closest point found.
+    fn nearest_neighbor_search<M: Metric<P>>(
+        &self,
+        point: &P,
+        node_index: usize,
+        split: usize,
+        metric: &M,
+        best: &mut (usize, M::Distance),
+    ) {
+        let node = &self.points[node_index];
+        let distance = metric.distance(point, node);
+        if distance < best.1 {
+            *best = (node_index, distance);
+        }
+
+        let left = 2 * node_index + 1;
+        let (primary, secondary) = if point.coord(split) <= node.coord(split) {
+            (left, left + 1)
+        } else {
+            (left + 1, left)
+        };
+        let next_split = (split + 1) % P::dim();
+        if primary < self.points.len() {
+            self.nearest_neighbor_search(point, primary, next_split, metric, best);
+        }
+        if secondary < self.points.len()
+            && best.1 > metric.plane_distance(point, split, node.coord(split))
+        {
+            self.nearest_neighbor_search(point, secondary, next_split, metric, best);
+        }
+    }
+}
+
+/// A view of a `StaticKDTree` whose queries measure distances with a [`Metric`], returned by
+/// [`StaticKDTree::using`]
+#[derive(Debug, Clone, Copy)]
+pub struct StaticMetricView<'a, P: KdPoint, V, M> {
+    /// Viewed tree
+    tree: &'a StaticKDTree<P, V>,
+    /// Metric used by the queries
+    metric: M,
+}
+
+impl<'a, P: KdPoint, V, M: Metric<P>> StaticMetricView<'a, P, V, M> {
+    /// Finds the nearest neighbor of the specified `point` and returns it with its value.
+    pub fn nearest_neighbor(&self, point: &P) -> Option<(P, &'a V)> {
+        let root = self.tree.points.first()?;
+        let mut best = (0, self.metric.distance(point, root));
+        self.tree
+            .nearest_neighbor_search(point, 0, 0, &self.metric, &mut best);
+        let (index, _) = best;
+        Some((self.tree.points[index], &self.tree.values[index]))
+    }
+}
+
+impl<P: KdPoint, V> From<KDTree<P, V>> for StaticKDTree<P, V> {
+    fn from(tree: KDTree<P, V>) -> Self {
+        StaticKDTree::from_entries(tree.into_entries())
+    }
+}
+
+/// Lays out the points of `order` in the subtree starting from `node_index` by storing their
+/// indices into `layout`. Each node is the point of rank `left_size` along its `split` axis, so
+/// that the points of its left subtree have a lower or equal coordinate and the points of its
+/// right subtree have a greater or equal coordinate.
+fn build_layout<P: KdPoint>(
+    points: &[P],
+    order: &mut [usize],
+    layout: &mut [usize],
+    node_index: usize,
+    split: usize,
+) {
+    if order.is_empty() {
+        return;
+    }
+    let median = left_size(order.len());
+    order.select_nth_unstable_by(median, |a, b| {
+        points[*a].coord(split).total_cmp(&points[*b].coord(split))
+    });
+    layout[node_index] = order[median];
+    let (left, right) = order.split_at_mut(median);
+    let right = &mut right[1..];
+    let next_split = (split + 1) % P::dim();
+    build_layout(points, left, layout, 2 * node_index + 1, next_split);
+    build_layout(points, right, layout, 2 * node_index + 2, next_split);
+}
+
+/// Returns the number of nodes in the left subtree of a complete binary tree of `size` nodes.
+fn left_size(size: usize) -> usize {
+    if size < 2 {
+        return 0;
+    }
+    // Index of the last level, and number of nodes of this level under the left subtree when full
+    let height = size.ilog2();
+    let half = 1 << (height - 1);
+    half - 1 + (size + 1 - (1 << height)).min(half)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric::Manhattan;
+    use crate::point::squared_distance;
+    use rand::random;
+
+    fn random_point() -> Point {
+        Point::new(random::<f32>(), random::<f32>())
+    }
+
+    #[test]
+    fn test_left_size() {
+        let sizes: Vec<usize> = (0..=8).map(left_size).collect();
+        assert_eq!(sizes, vec![0, 0, 1, 1, 2, 3, 3, 3, 4]);
+    }
+
+    #[test]
+    fn test_nearest_point() {
+        for _ in 0..100 {
+            let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+            let tree = StaticKDTree::from_points(&points);
+            let target = random_point();
+            let (nearest, _) = tree.nearest_neighbor(&target).unwrap();
+            let expected = points
+                .iter()
+                .map(|point| squared_distance(point, &target))
+                .min_by(f32::total_cmp)
+                .unwrap();
+            assert_eq!(squared_distance(&nearest, &target), expected);
+        }
+    }
+
+    #[test]
+    fn test_nearest_point_with_duplicates() {
+        // Coincident coordinates end up on both sides of the split planes
+        let points: Vec<[i32; 3]> = (0..1_000)
+            .map(|_| {
+                [
+                    random::<i32>() % 4,
+                    random::<i32>() % 4,
+                    random::<i32>() % 4,
+                ]
+            })
+            .collect();
+        let tree = StaticKDTree::from_points(&points);
+        assert_eq!(tree.len(), 1_000);
+        assert_eq!(tree.depth(), 10);
+        for point in points.iter() {
+            assert_eq!(tree.nearest_neighbor(point), Some((*point, &())));
+        }
+    }
+
+    #[test]
+    fn test_from_kdtree() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let mut tree = KDTree::default();
+        for (index, point) in points.iter().enumerate() {
+            tree.insert(*point, index);
+        }
+        let tree = StaticKDTree::from(tree);
+        assert_eq!(tree.len(), 1_000);
+        for (index, point) in points.iter().enumerate() {
+            assert_eq!(tree.nearest_neighbor(point), Some((*point, &index)));
+        }
+        let mut values: Vec<usize> = tree.iter().map(|(_, value)| *value).collect();
+        values.sort();
+        assert_eq!(values, (0..1_000).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_empty() {
+        let tree = StaticKDTree::<Point>::from_points(&[]);
+        assert!(tree.is_empty());
+        assert_eq!(tree.depth(), 0);
+        assert_eq!(tree.nearest_neighbor(&random_point()), None);
+    }
+
+    #[test]
+    fn test_nearest_point_with_metric() {
+        let points: Vec<Point> = (0..1_000).map(|_| random_point()).collect();
+        let tree = StaticKDTree::from_points(&points);
+        for _ in 0..100 {
+            let target = random_point();
+            let (nearest, _) = tree.using(Manhattan).nearest_neighbor(&target).unwrap();
+            let expected = points
+                .iter()
+                .map(|point| Manhattan.distance(point, &target))
+                .min_by(f32::total_cmp)
+                .unwrap();
+            assert_eq!(Manhattan.distance(&nearest, &target), expected);
+        }
+    }
+}